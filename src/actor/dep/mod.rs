@@ -1,24 +1,34 @@
-use std::collections::{
-    hash_map::{Entry, OccupiedEntry},
-    HashMap, HashSet,
+use std::{
+    collections::{
+        hash_map::{Entry, OccupiedEntry},
+        HashMap, HashSet, VecDeque,
+    },
+    time::{Duration, Instant},
 };
 
 use futures_util::{stream, StreamExt};
 use tap::Pipe;
 use tokio::{
-    sync::mpsc::{Receiver, Sender},
+    sync::{
+        mpsc::{Receiver, Sender},
+        oneshot,
+    },
     task::JoinHandle,
 };
 
 use crate::{
     actor::guard::is_guard_exists,
-    unit::{State, UnitDeps, UnitId},
+    unit::{Env, JobMode, State, UnitDeps, UnitId, UnitKind},
     Rc,
 };
 
+use self::transaction::{JobId, JobKind, Transaction, TransactionError};
+
+pub(crate) mod transaction;
+
 use super::{
     guard,
-    state::{self, get_state},
+    state::{self, get_state, list_states},
 };
 
 /// runtime mutable dep info, used to wait deps
@@ -33,14 +43,55 @@ enum JobWaitInfo {
 }
 
 impl JobWaitInfo {
+    fn kind(&self) -> JobKind {
+        match self {
+            JobWaitInfo::Start { .. } => JobKind::Start,
+            JobWaitInfo::Stop { .. } => JobKind::Stop,
+        }
+    }
+
     fn is_empty(&self) -> bool {
+        self.waiting().is_empty()
+    }
+
+    /// the units the job is still waiting for
+    fn waiting(&self) -> &HashSet<UnitId> {
         match self {
-            JobWaitInfo::Start { after } => after.is_empty(),
-            JobWaitInfo::Stop { before } => before.is_empty(),
+            JobWaitInfo::Start { after } => after,
+            JobWaitInfo::Stop { before } => before,
         }
     }
 }
 
+/// the units started again by upholds at most `START_LIMIT_BURST` times within `START_LIMIT_INTERVAL`, \
+/// same as the defaults of systemd's `StartLimitIntervalSec=` and `StartLimitBurst=`
+const START_LIMIT_INTERVAL: Duration = Duration::from_secs(10);
+const START_LIMIT_BURST: usize = 5;
+
+/// the recent starts of a unit, to stop upholding it once started too often
+#[derive(Debug, Default)]
+struct StartLimit {
+    starts: VecDeque<Instant>,
+}
+
+impl StartLimit {
+    /// record the start if the limit is not hit yet
+    fn try_start(&mut self, now: Instant) -> bool {
+        while self
+            .starts
+            .front()
+            .is_some_and(|&start| now.duration_since(start) >= START_LIMIT_INTERVAL)
+        {
+            self.starts.pop_front();
+        }
+        if self.starts.len() >= START_LIMIT_BURST {
+            return false;
+        }
+        self.starts.push_back(now);
+        true
+    }
+}
+
 // after is useless in ReverseDepInfo since what we want is triggers/blocking_relations here,
 // self will never block afters start
 #[derive(Default)]
@@ -52,21 +103,73 @@ struct FullDepInfo {
     before: HashSet<UnitId>,
     after: HashSet<UnitId>,
     conflicts: HashSet<UnitId>,
+    binds_to: HashSet<UnitId>,
+    bound_by: HashSet<UnitId>,
+    /// units which are part of me, the reverse of `part_of`, stopped and restarted along with me
+    consists_of: HashSet<UnitId>,
+    requisite: HashSet<UnitId>,
+    requisite_of: HashSet<UnitId>,
+    upholds: HashSet<UnitId>,
+    upheld_by: HashSet<UnitId>,
+    propagates_reload_to: HashSet<UnitId>,
+}
+
+impl FullDepInfo {
+    /// units to start along with me, and whether I require them
+    fn pulls_in(&self) -> impl Iterator<Item = (&UnitId, bool)> {
+        let required = self.requires.iter().chain(&self.binds_to);
+        let wanted = self.wants.iter().chain(&self.upholds);
+        required
+            .map(|unit| (unit, true))
+            .chain(wanted.map(|unit| (unit, false)))
+    }
+
+    /// units to stop along with me
+    fn stops_with(&self) -> impl Iterator<Item = &UnitId> {
+        self.required_by
+            .iter()
+            .chain(&self.bound_by)
+            .chain(&self.consists_of)
+            .chain(&self.requisite_of)
+    }
 }
 
 pub(crate) enum Message {
     /// Load depinfo of the unit
     Load(UnitId, Rc<UnitDeps>),
-    /// add a unit waiting to start
-    AddToStart(UnitId),
-    /// add a unit waiting to stop
-    AddToStop(UnitId),
+    /// turn the request into a transaction and apply it, replying the job id or why it is rejected \
+    /// the env is passed to the requested unit if this job starts it
+    Enqueue(UnitId, JobKind, JobMode, Env, Option<JobReply>),
     /// receive notify: state of the unit has changed
     StateChange(UnitId, State),
+    /// stop the unit, then start it and the units stopped along with it once they are dead, \
+    /// replying the id of the stop job, or of the start job if the unit is dead already
+    Restart(UnitId, Option<JobReply>),
+    /// reload the unit, and the ones it propagates reload to
+    Reload(UnitId),
 }
+pub(crate) type JobReply = oneshot::Sender<Result<JobId, TransactionError>>;
+
+/// a restart waiting for the units stopped by it to be dead, before starting them again
+#[derive(Debug)]
+struct Restart {
+    stopping: HashSet<UnitId>,
+    units: Vec<UnitId>,
+}
+
 pub(crate) struct DepStore {
     pending_jobs: HashMap<UnitId, JobWaitInfo>,
+    last_job_id: JobId,
     dep_map: HashMap<UnitId, FullDepInfo>,
+    /// the deps of each unit as loaded, to build `dep_map` again when a unit is updated
+    unit_deps: HashMap<UnitId, Rc<UnitDeps>>,
+    /// units whose depinfo is loaded
+    loaded: HashSet<UnitId>,
+    /// mount units not loaded yet -> units waiting to require them, see `requires_mounts_for`
+    mounts_for: HashMap<UnitId, HashSet<UnitId>>,
+    /// the starts by upholds, see `StartLimit`
+    uphold_starts: HashMap<UnitId, StartLimit>,
+    restarts: Vec<Restart>,
     dep: Sender<Message>,
     state: Sender<state::Message>,
     guard: Sender<guard::Message>,
@@ -80,7 +183,13 @@ impl DepStore {
     ) -> Self {
         Self {
             pending_jobs: Default::default(),
+            last_job_id: 0,
             dep_map: Default::default(),
+            unit_deps: Default::default(),
+            loaded: Default::default(),
+            mounts_for: Default::default(),
+            uphold_starts: Default::default(),
+            restarts: Default::default(),
             dep,
             state,
             guard,
@@ -91,29 +200,46 @@ impl DepStore {
             while let Some(msg) = rx.recv().await {
                 match msg {
                     Message::Load(id, deps) => {
-                        let dmap = &mut self.dep_map;
-                        dep_map_insert(&id, &deps.requires, dmap, |rdep| &mut rdep.requires);
-                        dep_map_insert(&id, &deps.wants, dmap, |rdep| &mut rdep.wants);
-                        dep_map_insert(&id, &deps.after, dmap, |rdep| &mut rdep.after);
-                        dep_map_insert(&id, &deps.before, dmap, |rdep| &mut rdep.before);
-                        dep_map_insert(&id, &deps.conflicts, dmap, |rdep| &mut rdep.conflicts);
-
-                        dep_map_rinsert(&id, &deps.requires, dmap, |rdep| &mut rdep.required_by);
-                        dep_map_rinsert(&id, &deps.wants, dmap, |rdep| &mut rdep.wanted_by);
-                        dep_map_rinsert(&id, &deps.after, dmap, |rdep| &mut rdep.before);
-                        dep_map_rinsert(&id, &deps.before, dmap, |rdep| &mut rdep.after);
-                        dep_map_rinsert(&id, &deps.conflicts, dmap, |rdep| &mut rdep.conflicts);
+                        if self.unit_deps.insert(id.clone(), deps.clone()).is_some() {
+                            // the old deps may be gone, build the map again from all the units
+                            self.rebuild_dep_map();
+                        } else {
+                            self.load_deps(id, &deps);
+                        }
                     }
-                    Message::AddToStart(id) => self.add_to_start(id).await,
-                    Message::AddToStop(id) => self.add_to_stop(id).await,
+                    Message::Enqueue(id, kind, mode, env, reply) => {
+                        let result = self.enqueue(id, kind, mode, env).await;
+                        if let Err(e) = &result {
+                            println!("dep: {}", e);
+                        }
+                        if let Some(reply) = reply {
+                            reply.send(result).ok();
+                        }
+                    }
+                    Message::Restart(id, reply) => {
+                        let result = self.restart(id).await;
+                        if let Err(e) = &result {
+                            println!("dep: {}", e);
+                        }
+                        if let Some(reply) = reply {
+                            reply.send(result).ok();
+                        }
+                    }
+                    Message::Reload(id) => self.reload(id).await,
                     Message::StateChange(state_change_id, new_state) => {
                         let Self {
                             pending_jobs,
                             dep_map,
+                            uphold_starts,
+                            restarts,
                             dep,
                             state,
                             guard,
+                            ..
                         } = &mut self;
+                        if new_state.is_dead() {
+                            handle_restarts(restarts, &state_change_id, dep).await;
+                        }
                         if let Entry::Occupied(full_dep) = dep_map.entry(state_change_id.clone()) {
                             let full_dep = full_dep.get();
                             match new_state {
@@ -121,16 +247,40 @@ impl DepStore {
                                 State::Stopped => {
                                     handle_stopped(full_dep, pending_jobs, &state_change_id, guard)
                                         .await;
+                                    handle_dead(
+                                        full_dep,
+                                        pending_jobs,
+                                        uphold_starts,
+                                        &state_change_id,
+                                        dep,
+                                        state,
+                                    )
+                                    .await;
                                 }
                                 State::Failed => {
-                                    // rule 3: failed, requires should fail
-                                    for waiting_id in full_dep.required_by.iter().cloned() {
-                                        pending_jobs.remove(&waiting_id);
-                                        guard
-                                            .send(guard::Message::DepsFailed(waiting_id))
-                                            .await
-                                            .unwrap()
+                                    // rule 3: failed, requires and binds_to waiting to start should fail
+                                    for waiting_id in
+                                        full_dep.required_by.union(&full_dep.bound_by).cloned()
+                                    {
+                                        if let Entry::Occupied(o) = pending_jobs.entry(waiting_id) {
+                                            if o.get().kind() == JobKind::Start {
+                                                let (waiting_id, _) = o.remove_entry();
+                                                guard
+                                                    .send(guard::Message::DepsFailed(waiting_id))
+                                                    .await
+                                                    .unwrap()
+                                            }
+                                        }
                                     }
+                                    handle_dead(
+                                        full_dep,
+                                        pending_jobs,
+                                        uphold_starts,
+                                        &state_change_id,
+                                        dep,
+                                        state,
+                                    )
+                                    .await;
                                 }
                                 State::Starting => {
                                     // starting: things required by me should start
@@ -153,51 +303,210 @@ impl DepStore {
         })
     }
 
-    async fn add_to_start(&mut self, id: UnitId) {
-        // simple job merge
-        let deps = self.dep_map.get(&id).unwrap();
-        if let Entry::Occupied(o) = self.pending_jobs.entry(id.clone()) {
-            match o.get() {
-                // since there's already waiting for its deps
-                // dont need to insert another time
-                JobWaitInfo::Start { .. } => return,
-                // remove the previous stop job here
-                JobWaitInfo::Stop { .. } => {
-                    o.remove();
+    /// add the deps of the unit, and the reverse ones to the units it refers to
+    fn load_deps(&mut self, id: UnitId, deps: &UnitDeps) {
+        let dmap = &mut self.dep_map;
+        dmap.entry(id.clone()).or_default();
+        dep_map_insert(&id, &deps.requires, dmap, |rdep| &mut rdep.requires);
+        dep_map_insert(&id, &deps.wants, dmap, |rdep| &mut rdep.wants);
+        dep_map_insert(&id, &deps.after, dmap, |rdep| &mut rdep.after);
+        dep_map_insert(&id, &deps.before, dmap, |rdep| &mut rdep.before);
+        dep_map_insert(&id, &deps.conflicts, dmap, |rdep| &mut rdep.conflicts);
+        dep_map_insert(&id, &deps.binds_to, dmap, |rdep| &mut rdep.binds_to);
+        dep_map_insert(&id, &deps.requisite, dmap, |rdep| &mut rdep.requisite);
+        dep_map_insert(&id, &deps.upholds, dmap, |rdep| &mut rdep.upholds);
+        dep_map_insert(&id, &deps.propagates_reload_to, dmap, |rdep| {
+            &mut rdep.propagates_reload_to
+        });
+
+        dep_map_rinsert(&id, &deps.requires, dmap, |rdep| &mut rdep.required_by);
+        dep_map_rinsert(&id, &deps.wants, dmap, |rdep| &mut rdep.wanted_by);
+        dep_map_rinsert(&id, &deps.after, dmap, |rdep| &mut rdep.before);
+        dep_map_rinsert(&id, &deps.before, dmap, |rdep| &mut rdep.after);
+        dep_map_rinsert(&id, &deps.conflicts, dmap, |rdep| &mut rdep.conflicts);
+        dep_map_rinsert(&id, &deps.binds_to, dmap, |rdep| &mut rdep.bound_by);
+        dep_map_rinsert(&id, &deps.part_of, dmap, |rdep| &mut rdep.consists_of);
+        dep_map_rinsert(&id, &deps.requisite, dmap, |rdep| &mut rdep.requisite_of);
+        dep_map_rinsert(&id, &deps.upholds, dmap, |rdep| &mut rdep.upheld_by);
+
+        // reverse deps declared by this unit
+        dep_map_insert(&id, &deps.required_by, dmap, |rdep| &mut rdep.required_by);
+        dep_map_insert(&id, &deps.wanted_by, dmap, |rdep| &mut rdep.wanted_by);
+        dep_map_rinsert(&id, &deps.required_by, dmap, |rdep| &mut rdep.requires);
+        dep_map_rinsert(&id, &deps.wanted_by, dmap, |rdep| &mut rdep.wants);
+
+        // only the loaded mount units are required
+        for mount in deps.requires_mounts_for.iter() {
+            if self.loaded.contains(mount) {
+                require_mount(&id, mount, dmap);
+            } else {
+                self.mounts_for
+                    .entry(mount.clone())
+                    .or_default()
+                    .insert(id.clone());
+            }
+        }
+        for unit in self.mounts_for.remove(&id).unwrap_or_default() {
+            require_mount(&unit, &id, dmap);
+        }
+        self.loaded.insert(id);
+    }
+
+    fn rebuild_dep_map(&mut self) {
+        self.dep_map.clear();
+        self.loaded.clear();
+        self.mounts_for.clear();
+        let units: Vec<(UnitId, Rc<UnitDeps>)> = self
+            .unit_deps
+            .iter()
+            .map(|(id, deps)| (id.clone(), deps.clone()))
+            .collect();
+        for (id, deps) in units {
+            self.load_deps(id, &deps);
+        }
+    }
+
+    /// build the transaction of the request, check it against the pending jobs, then apply it
+    async fn enqueue(
+        &mut self,
+        id: UnitId,
+        kind: JobKind,
+        mode: JobMode,
+        env: Env,
+    ) -> Result<JobId, TransactionError> {
+        let states = list_states(&self.state).await;
+        let transaction = Transaction::new(
+            &self.dep_map,
+            &self.loaded,
+            &states,
+            &self.pending_jobs,
+            id.clone(),
+            kind,
+            mode,
+        )?;
+        Ok(self.apply(transaction, id, kind, mode, env).await)
+    }
+
+    /// stop the unit and the units stopping with it, and start the running ones again once all of them are dead, \
+    /// like a restart job propagated to `part_of`, `requires` and `binds_to`
+    async fn restart(&mut self, id: UnitId) -> Result<JobId, TransactionError> {
+        let states = list_states(&self.state).await;
+        let is_dead = |unit: &UnitId| states.get(unit).is_none_or(State::is_dead);
+        if is_dead(&id) {
+            return self
+                .enqueue(id, JobKind::Start, JobMode::Replace, Env::new())
+                .await;
+        }
+        let transaction = Transaction::new(
+            &self.dep_map,
+            &self.loaded,
+            &states,
+            &self.pending_jobs,
+            id.clone(),
+            JobKind::Stop,
+            JobMode::Replace,
+        )?;
+        let stopping: HashSet<UnitId> = transaction
+            .jobs
+            .keys()
+            .filter(|&unit| !is_dead(unit))
+            .cloned()
+            .collect();
+        // the unit itself first, the others may be after it
+        let units = std::iter::once(id.clone())
+            .chain(stopping.iter().filter(|&unit| *unit != id).cloned())
+            .collect();
+        let job = self
+            .apply(transaction, id, JobKind::Stop, JobMode::Replace, Env::new())
+            .await;
+        self.restarts.push(Restart { stopping, units });
+        Ok(job)
+    }
+
+    /// dispatch the jobs of the transaction, the env goes to the start job of the unit requested
+    async fn apply(
+        &mut self,
+        transaction: Transaction,
+        id: UnitId,
+        kind: JobKind,
+        mode: JobMode,
+        mut env: Env,
+    ) -> JobId {
+        self.last_job_id += 1;
+        println!(
+            "dep: job {}: {:?} {} with {} jobs",
+            self.last_job_id,
+            kind,
+            id,
+            transaction.jobs.len()
+        );
+        for (unit, &kind) in transaction.jobs.iter() {
+            match kind {
+                JobKind::Start => {
+                    let env = if *unit == id {
+                        std::mem::take(&mut env)
+                    } else {
+                        Env::new()
+                    };
+                    self.start_job(unit.clone(), &transaction, mode, env).await
                 }
+                JobKind::Stop => self.stop_job(unit.clone(), &transaction, mode).await,
+            }
+        }
+        self.last_job_id
+    }
+
+    /// merge the start job with the pending one, and dispatch it once its afters are active \
+    /// the env is dropped if the unit is starting or active already
+    async fn start_job(&mut self, id: UnitId, transaction: &Transaction, mode: JobMode, env: Env) {
+        let deps = self.dep_map.get(&id).unwrap();
+        match self.pending_jobs.get(&id).map(JobWaitInfo::kind) {
+            // since there's already waiting for its deps
+            // dont need to insert another time
+            Some(JobKind::Start) => return,
+            // replace the previous stop job here
+            Some(JobKind::Stop) => {
+                self.pending_jobs.remove(&id);
+            }
+            None if get_state(&self.state, id.clone()).await.is_active() => {
+                handle_active(deps, &mut self.pending_jobs, id, &self.guard).await;
+                return;
+            }
+            None => {
+                self.guard
+                    .send(guard::Message::Insert(id.clone(), env))
+                    .await
+                    .unwrap();
             }
-        } else if get_state(&self.state, id.clone()).await.is_active() {
-            handle_active(deps, &mut self.pending_jobs, id, &self.guard).await;
-            return;
-        } else {
-            self.guard
-                .send(guard::Message::Insert(id.clone()))
-                .await
-                .unwrap();
         }
         // build wait list: find what is blocking the start operation
+        // only the afters going to start are blocking, ordering alone never starts a unit,
+        // like systemd, otherwise a service started directly would wait forever on the socket
+        // it is implicitly after, when nothing starts that socket
+        let pending_jobs = &self.pending_jobs;
+        let state = &self.state;
+        let afters = match mode {
+            JobMode::IgnoreDependencies => Vec::new(),
+            _ => deps.after.iter().cloned().collect(),
+        };
         let wait_list = JobWaitInfo::Start {
-            after: stream::iter(deps.after.iter().cloned())
+            after: stream::iter(afters)
                 .filter(|after| {
+                    let going_to_start = transaction.get(after) == Some(JobKind::Start)
+                        || pending_jobs.get(after).map(JobWaitInfo::kind) == Some(JobKind::Start);
                     let after = after.clone();
-                    async { !get_state(&self.state, after).await.is_active() }
+                    async move {
+                        match get_state(state, after).await {
+                            State::Starting => true,
+                            state => going_to_start && !state.is_active(),
+                        }
+                    }
                 })
                 .collect()
                 .await,
         };
 
-        // trigger deps
         println!("dep: adding {} to start list", id);
-        // add requires && wants to start; add conflicts to stop
-        println!("dep: requires and wants:");
-        for unit_id in deps.requires.union(&deps.wants).cloned() {
-            println!("dep:     {}", unit_id);
-            self.dep.send(Message::AddToStart(unit_id)).await.unwrap();
-        }
-        for unit_id in deps.conflicts.iter().cloned() {
-            self.dep.send(Message::AddToStop(unit_id)).await.unwrap();
-        }
-
         // trigger action or add to wait list
         if wait_list.is_empty() {
             self.guard
@@ -209,48 +518,80 @@ impl DepStore {
         }
     }
 
-    async fn add_to_stop(&mut self, id: UnitId) {
+    /// merge the stop job with the pending one, and dispatch it once its befores are dead
+    async fn stop_job(&mut self, id: UnitId, transaction: &Transaction, mode: JobMode) {
         println!("adding {} to stop", id);
         let deps = self.dep_map.get(&id).unwrap();
-        // simple job merge
-        if let Entry::Occupied(o) = self.pending_jobs.entry(id.clone()) {
-            match o.get() {
-                // remove the previous start job here
-                JobWaitInfo::Start { .. } => {
-                    o.remove();
-                }
-                // since there's already waiting for its deps
-                // dont need to insert another time
-                JobWaitInfo::Stop { .. } => return,
+        match self.pending_jobs.get(&id).map(JobWaitInfo::kind) {
+            // since there's already waiting for its deps
+            // dont need to insert another time
+            Some(JobKind::Stop) => return,
+            // replace the previous start job here
+            Some(JobKind::Start) => {
+                self.pending_jobs.remove(&id);
             }
-        } else if !is_guard_exists(&self.guard, id.clone()).await {
-            handle_stopped(deps, &mut self.pending_jobs, &id, &self.guard).await;
-            return;
+            None if !is_guard_exists(&self.guard, id.clone()).await => {
+                handle_stopped(deps, &mut self.pending_jobs, &id, &self.guard).await;
+                return;
+            }
+            None => (),
         }
 
         // build wait_list: find what is blocking the stop operation
+        // only the befores going to stop are blocking
+        let pending_jobs = &self.pending_jobs;
+        let state = &self.state;
+        let befores = match mode {
+            JobMode::IgnoreDependencies => Vec::new(),
+            _ => deps.before.iter().cloned().collect(),
+        };
         let wait_list = JobWaitInfo::Stop {
-            before: stream::iter(deps.before.iter().cloned())
+            before: stream::iter(befores)
                 .filter(|before| {
+                    let going_to_stop = transaction.get(before) == Some(JobKind::Stop)
+                        || pending_jobs.get(before).map(JobWaitInfo::kind) == Some(JobKind::Stop);
                     let before = before.clone();
-                    async { !get_state(&self.state, before).await.is_stopped() }
+                    async move {
+                        match get_state(state, before).await {
+                            State::Stopping => true,
+                            state => going_to_stop && !state.is_dead(),
+                        }
+                    }
                 })
                 .collect()
                 .await,
         };
 
-        for unit_id in deps.required_by.iter().cloned() {
-            self.dep.send(Message::AddToStop(unit_id)).await.unwrap();
-        }
-
         // trigger or add to wait list
         if wait_list.is_empty() {
             self.guard.send(guard::Message::Stop(id)).await.unwrap();
         } else {
-            // add id.required_by to stop
             self.pending_jobs.insert(id, wait_list);
         }
     }
+
+    async fn reload(&mut self, id: UnitId) {
+        let mut reloaded = HashSet::new();
+        let mut queue = vec![id];
+        while let Some(unit) = queue.pop() {
+            if !reloaded.insert(unit.clone()) {
+                continue;
+            }
+            if let Some(deps) = self.dep_map.get(&unit) {
+                queue.extend(deps.propagates_reload_to.iter().cloned());
+            }
+            println!("dep: reloading {}", unit);
+            self.guard.send(guard::Message::Reload(unit)).await.unwrap();
+        }
+    }
+}
+
+/// the units kept running when isolating, like systemd's `IgnoreOnIsolate=`
+pub(crate) fn ignore_on_isolate(id: &UnitId) -> bool {
+    matches!(
+        id.kind(),
+        UnitKind::Mount | UnitKind::Swap | UnitKind::Device
+    )
 }
 
 async fn handle_active(
@@ -259,14 +600,86 @@ async fn handle_active(
     state_change_id: UnitId,
     guard: &Sender<guard::Message>,
 ) {
-    // rule 4: active, requires/wants after me can start
-    for waiting_id in &full_dep.before & &(&full_dep.required_by | &full_dep.wanted_by) {
+    // rule 4: active, units after me can start
+    for waiting_id in full_dep.before.iter().cloned() {
         if let Entry::Occupied(o) = pending_jobs.entry(waiting_id) {
             tick_start_waiting(o, &state_change_id, guard).await;
         }
     }
 }
 
+/// rule 5: dead for any reason, units bound to me should stop, and the units upholding me start me again, \
+/// until the start limit is hit
+async fn handle_dead(
+    full_dep: &FullDepInfo,
+    pending_jobs: &HashMap<UnitId, JobWaitInfo>,
+    uphold_starts: &mut HashMap<UnitId, StartLimit>,
+    state_change_id: &UnitId,
+    dep: &Sender<Message>,
+    state: &Sender<state::Message>,
+) {
+    for bound in full_dep.bound_by.iter().cloned() {
+        if get_state(state, bound.clone()).await.is_active() {
+            dep.send(Message::Enqueue(
+                bound,
+                JobKind::Stop,
+                JobMode::Replace,
+                Env::new(),
+                None,
+            ))
+            .await
+            .unwrap();
+        }
+    }
+    for upholder in full_dep.upheld_by.iter().cloned() {
+        let stopping = matches!(pending_jobs.get(&upholder), Some(JobWaitInfo::Stop { .. }));
+        if !stopping && get_state(state, upholder).await.is_active() {
+            let id = state_change_id.clone();
+            let limit = uphold_starts.entry(id.clone()).or_default();
+            if !limit.try_start(Instant::now()) {
+                println!("dep: {} started too often, not upholding it any more", id);
+                break;
+            }
+            dep.send(Message::Enqueue(
+                id,
+                JobKind::Start,
+                JobMode::Replace,
+                Env::new(),
+                None,
+            ))
+            .await
+            .unwrap();
+            break;
+        }
+    }
+}
+
+/// the unit is dead, start the units of the restarts no longer waiting for anything
+async fn handle_restarts(
+    restarts: &mut Vec<Restart>,
+    state_change_id: &UnitId,
+    dep: &Sender<Message>,
+) {
+    for restart in restarts.iter_mut() {
+        restart.stopping.remove(state_change_id);
+    }
+    let (done, waiting) = std::mem::take(restarts)
+        .into_iter()
+        .partition(|restart| restart.stopping.is_empty());
+    *restarts = waiting;
+    for unit in done.into_iter().flat_map(|restart: Restart| restart.units) {
+        dep.send(Message::Enqueue(
+            unit,
+            JobKind::Start,
+            JobMode::Replace,
+            Env::new(),
+            None,
+        ))
+        .await
+        .unwrap();
+    }
+}
+
 async fn handle_stopped(
     full_dep: &FullDepInfo,
     pending_jobs: &mut HashMap<UnitId, JobWaitInfo>,
@@ -279,8 +692,8 @@ async fn handle_stopped(
             tick_start_waiting(o, state_change_id, guard).await;
         }
     }
-    // rule 2: stopped, requires before me can stop
-    for waiting_id in &full_dep.after & &full_dep.requires {
+    // rule 2: stopped, units before me can stop
+    for waiting_id in full_dep.after.iter().cloned() {
         if let Entry::Occupied(o) = pending_jobs.entry(waiting_id) {
             tick_stop_waiting(o, state_change_id, guard).await;
         }
@@ -315,6 +728,15 @@ async fn tick_start_waiting(
     }
 }
 
+/// `requires` + `after` from the unit to the mount unit
+fn require_mount(unit: &UnitId, mount: &UnitId, target: &mut HashMap<UnitId, FullDepInfo>) {
+    let mount = std::slice::from_ref(mount);
+    dep_map_insert(unit, mount, target, |rdep| &mut rdep.requires);
+    dep_map_insert(unit, mount, target, |rdep| &mut rdep.after);
+    dep_map_rinsert(unit, mount, target, |rdep| &mut rdep.required_by);
+    dep_map_rinsert(unit, mount, target, |rdep| &mut rdep.before);
+}
+
 fn dep_map_insert(
     unit0: &UnitId,
     src: &[UnitId],
@@ -341,3 +763,23 @@ fn dep_map_rinsert(
             .insert(unit0.clone());
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{StartLimit, START_LIMIT_BURST, START_LIMIT_INTERVAL};
+
+    #[test]
+    fn test_start_limit() {
+        let mut limit = StartLimit::default();
+        let now = Instant::now();
+        for i in 0..START_LIMIT_BURST {
+            assert!(limit.try_start(now + Duration::from_secs(i as u64)));
+        }
+        assert!(!limit.try_start(now + Duration::from_secs(5)));
+        // the first start is out of the interval
+        assert!(limit.try_start(now + START_LIMIT_INTERVAL));
+        assert!(!limit.try_start(now + START_LIMIT_INTERVAL));
+    }
+}
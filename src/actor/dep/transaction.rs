@@ -0,0 +1,646 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+};
+
+use super::{ignore_on_isolate, FullDepInfo, JobWaitInfo};
+use crate::unit::{JobMode, State, UnitId};
+
+/// id of the transaction applied, increasing from 1
+pub(crate) type JobId = u64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum JobKind {
+    Start,
+    Stop,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum TransactionError {
+    /// the unit to start or require is not loaded
+    NotLoaded(UnitId),
+    /// the unit should both start and stop
+    Conflict(UnitId),
+    /// the unit has a pending job of the other kind, which is not replaced in `fail` mode
+    JobConflict(UnitId),
+    /// `isolate` only applies to start jobs
+    IsolateStop(UnitId),
+    /// the jobs wait for each other due to after/before, and all of them are required
+    OrderingCycle(Vec<UnitId>),
+    /// the requisite of the unit to start is not active, `(unit, requisite)`
+    RequisiteNotActive(UnitId, UnitId),
+}
+
+impl Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionError::NotLoaded(id) => write!(f, "unit {} is not loaded", id),
+            TransactionError::Conflict(id) => {
+                write!(f, "transaction contains conflicting jobs for {}", id)
+            }
+            TransactionError::JobConflict(id) => {
+                write!(f, "{} has a conflicting job, not replaced in fail mode", id)
+            }
+            TransactionError::IsolateStop(id) => write!(f, "can not isolate to stop {}", id),
+            TransactionError::OrderingCycle(cycle) => {
+                write!(f, "ordering cycle {}", cycle_path(cycle))
+            }
+            TransactionError::RequisiteNotActive(id, requisite) => {
+                write!(f, "requisite {} of {} is not active", requisite, id)
+            }
+        }
+    }
+}
+
+/// all the jobs of a request, built before any of them applied
+#[derive(Debug, Default)]
+pub(super) struct Transaction {
+    pub(super) jobs: BTreeMap<UnitId, JobKind>,
+    /// jobs pulled in only through required deps, they can not be dropped
+    required: HashSet<UnitId>,
+    /// wants-only jobs dropped to break ordering cycles, or whose requisites are not active
+    dropped: HashSet<UnitId>,
+}
+
+/// `a -> b -> a`
+fn cycle_path(cycle: &[UnitId]) -> String {
+    cycle
+        .iter()
+        .chain(cycle.first())
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+impl Transaction {
+    /// the job of the unit and the jobs pulled in by it, only the unit itself with `ignore-*` modes, \
+    /// plus stopping all the other units with `isolate` \
+    /// ordering cycles and inactive requisites drop a wants-only job, or fail the transaction
+    pub(super) fn new(
+        dep_map: &HashMap<UnitId, FullDepInfo>,
+        loaded: &HashSet<UnitId>,
+        states: &HashMap<UnitId, State>,
+        pending_jobs: &HashMap<UnitId, JobWaitInfo>,
+        id: UnitId,
+        kind: JobKind,
+        mode: JobMode,
+    ) -> Result<Self, TransactionError> {
+        let transaction = Self::build(dep_map, loaded, states, pending_jobs, id, kind, mode)?;
+        if mode == JobMode::Fail {
+            for (unit, &kind) in transaction.jobs.iter() {
+                if pending_jobs.get(unit).is_some_and(|job| job.kind() != kind) {
+                    return Err(TransactionError::JobConflict(unit.clone()));
+                }
+            }
+        }
+        Ok(transaction)
+    }
+
+    fn build(
+        dep_map: &HashMap<UnitId, FullDepInfo>,
+        loaded: &HashSet<UnitId>,
+        states: &HashMap<UnitId, State>,
+        pending_jobs: &HashMap<UnitId, JobWaitInfo>,
+        id: UnitId,
+        kind: JobKind,
+        mode: JobMode,
+    ) -> Result<Self, TransactionError> {
+        if !loaded.contains(&id) {
+            return Err(TransactionError::NotLoaded(id));
+        }
+        if mode == JobMode::Isolate && kind == JobKind::Stop {
+            return Err(TransactionError::IsolateStop(id));
+        }
+        let mut transaction = Self::default();
+        loop {
+            transaction.jobs.clear();
+            transaction.required.clear();
+            match mode {
+                // ordering is ignored too, so there is no cycle to wait on
+                JobMode::IgnoreDependencies => {
+                    transaction.jobs.insert(id, kind);
+                    return Ok(transaction);
+                }
+                JobMode::IgnoreRequirements => {
+                    transaction.jobs.insert(id.clone(), kind);
+                    transaction.required.insert(id.clone());
+                }
+                _ => transaction.add(dep_map, loaded, id.clone(), kind)?,
+            }
+            if mode == JobMode::Isolate {
+                transaction.add_isolate_stops(dep_map, loaded, states);
+            }
+            // requisites are never started, they should be active already
+            if mode != JobMode::IgnoreRequirements {
+                if let Some((unit, requisite)) = transaction.inactive_requisite(dep_map, states) {
+                    if transaction.required.contains(&unit) {
+                        return Err(TransactionError::RequisiteNotActive(unit, requisite));
+                    }
+                    println!(
+                        "requisite {} of {} is not active, dropping its job",
+                        requisite, unit
+                    );
+                    transaction.dropped.insert(unit);
+                    continue;
+                }
+            }
+            let Some(cycle) = transaction.find_cycle(dep_map, pending_jobs) else {
+                return Ok(transaction);
+            };
+            // the pending jobs stay, so do the jobs merged into them
+            let Some(drop) = cycle
+                .iter()
+                .filter(|&unit| !transaction.required.contains(unit))
+                .filter(|&unit| !transaction.is_merged(unit, pending_jobs))
+                .filter(|&unit| transaction.jobs.contains_key(unit))
+                .min()
+            else {
+                println!("ordering cycle {}, all jobs required", cycle_path(&cycle));
+                return Err(TransactionError::OrderingCycle(cycle));
+            };
+            println!(
+                "ordering cycle {}, dropping the job of {}",
+                cycle_path(&cycle),
+                drop
+            );
+            transaction.dropped.insert(drop.clone());
+        }
+    }
+
+    /// add the job, and the jobs it pulls in
+    fn add(
+        &mut self,
+        dep_map: &HashMap<UnitId, FullDepInfo>,
+        loaded: &HashSet<UnitId>,
+        id: UnitId,
+        kind: JobKind,
+    ) -> Result<(), TransactionError> {
+        let mut queue = vec![(id, kind, true)];
+        while let Some((id, kind, required)) = queue.pop() {
+            if !required && self.dropped.contains(&id) {
+                continue;
+            }
+            match self.jobs.get(&id) {
+                // pulled in again through required deps, so are the jobs it pulls in
+                Some(&k) if k == kind && (!required || self.required.contains(&id)) => continue,
+                Some(&k) if k == kind => (),
+                Some(_) => return Err(TransactionError::Conflict(id)),
+                None => (),
+            }
+            self.jobs.insert(id.clone(), kind);
+            if required {
+                self.required.insert(id.clone());
+            }
+            let Some(deps) = dep_map.get(&id) else {
+                continue;
+            };
+            let mut next: Vec<(UnitId, JobKind, bool)> = match kind {
+                JobKind::Start => {
+                    // the missing requirements fail the request, the missing wants are skipped
+                    let mut requires = deps.requires.iter().chain(&deps.binds_to);
+                    if let Some(missing) = requires.find(|&unit| !loaded.contains(unit)) {
+                        return Err(TransactionError::NotLoaded(missing.clone()));
+                    }
+                    let starts = deps
+                        .pulls_in()
+                        .map(|(unit, req)| (unit.clone(), JobKind::Start, required && req));
+                    let stops = deps
+                        .conflicts
+                        .iter()
+                        .map(|unit| (unit.clone(), JobKind::Stop, required));
+                    starts.chain(stops).collect()
+                }
+                JobKind::Stop => deps
+                    .stops_with()
+                    .map(|unit| (unit.clone(), JobKind::Stop, required))
+                    .collect(),
+            };
+            next.retain(|(unit, _, _)| loaded.contains(unit));
+            // sorted, so that the same request always builds the same transaction
+            next.sort_by(|(a, _, _), (b, _, _)| b.cmp(a));
+            queue.extend(next);
+        }
+        Ok(())
+    }
+
+    /// stop the running units without a job, except their requisites and `ignore_on_isolate` \
+    /// the units are stopped but not the ones depending on them
+    fn add_isolate_stops(
+        &mut self,
+        dep_map: &HashMap<UnitId, FullDepInfo>,
+        loaded: &HashSet<UnitId>,
+        states: &HashMap<UnitId, State>,
+    ) {
+        let mut keep: HashSet<&UnitId> = self.jobs.keys().collect();
+        for unit in self.jobs.keys() {
+            if let Some(deps) = dep_map.get(unit) {
+                keep.extend(deps.requisite.iter());
+            }
+        }
+        let stops: Vec<UnitId> = loaded
+            .iter()
+            .filter(|&unit| !keep.contains(unit) && !ignore_on_isolate(unit))
+            .filter(|&unit| states.get(unit).is_some_and(|state| !state.is_dead()))
+            .cloned()
+            .collect();
+        for unit in stops {
+            self.required.insert(unit.clone());
+            self.jobs.insert(unit, JobKind::Stop);
+        }
+    }
+
+    /// the first start job with a requisite not active, and the requisite
+    fn inactive_requisite(
+        &self,
+        dep_map: &HashMap<UnitId, FullDepInfo>,
+        states: &HashMap<UnitId, State>,
+    ) -> Option<(UnitId, UnitId)> {
+        self.jobs
+            .iter()
+            .filter(|(_, &kind)| kind == JobKind::Start)
+            .filter_map(|(id, _)| Some((id, dep_map.get(id)?)))
+            .find_map(|(id, deps)| {
+                let mut requisites: Vec<&UnitId> = deps.requisite.iter().collect();
+                requisites.sort();
+                requisites
+                    .into_iter()
+                    .find(|&unit| !states.get(unit).is_some_and(State::is_active))
+                    .map(|requisite| (id.clone(), requisite.clone()))
+            })
+    }
+
+    /// whether the job is merged into the pending one of the same kind, which keeps its wait list
+    fn is_merged(&self, id: &UnitId, pending_jobs: &HashMap<UnitId, JobWaitInfo>) -> bool {
+        pending_jobs
+            .get(id)
+            .is_some_and(|job| self.get(id) == Some(job.kind()))
+    }
+
+    /// the first cycle of jobs waiting for each other, starts wait on afters and stops on befores \
+    /// the pending jobs are in the graph too, waiting on what they are still waiting for
+    fn find_cycle(
+        &self,
+        dep_map: &HashMap<UnitId, FullDepInfo>,
+        pending_jobs: &HashMap<UnitId, JobWaitInfo>,
+    ) -> Option<Vec<UnitId>> {
+        let waits_on = |id: &UnitId| -> Vec<UnitId> {
+            let mut units: Vec<UnitId> = match (self.get(id), pending_jobs.get(id)) {
+                (Some(_), Some(job)) if self.is_merged(id, pending_jobs) => {
+                    job.waiting().iter().cloned().collect()
+                }
+                (None, Some(job)) => job.waiting().iter().cloned().collect(),
+                (Some(kind), _) => {
+                    let Some(deps) = dep_map.get(id) else {
+                        return Vec::new();
+                    };
+                    let order = match kind {
+                        JobKind::Start => &deps.after,
+                        JobKind::Stop => &deps.before,
+                    };
+                    order
+                        .iter()
+                        .filter(|&unit| {
+                            self.get(unit) == Some(kind)
+                                || pending_jobs.get(unit).map(JobWaitInfo::kind) == Some(kind)
+                        })
+                        .cloned()
+                        .collect()
+                }
+                (None, None) => Vec::new(),
+            };
+            units.sort();
+            units
+        };
+        // depth first from each job, a unit still on the path closes a cycle
+        let mut visited: HashSet<UnitId> = HashSet::new();
+        for root in self.jobs.keys() {
+            if visited.contains(root) {
+                continue;
+            }
+            let mut path: Vec<UnitId> = Vec::new();
+            let mut stack: Vec<(UnitId, Vec<UnitId>)> = Vec::new();
+            visited.insert(root.clone());
+            path.push(root.clone());
+            stack.push((root.clone(), waits_on(root)));
+            while let Some((_, next)) = stack.last_mut() {
+                let Some(unit) = next.pop() else {
+                    stack.pop();
+                    path.pop();
+                    continue;
+                };
+                if let Some(pos) = path.iter().position(|id| id == &unit) {
+                    return Some(path.split_off(pos));
+                }
+                if visited.insert(unit.clone()) {
+                    path.push(unit.clone());
+                    let next = waits_on(&unit);
+                    stack.push((unit, next));
+                }
+            }
+        }
+        None
+    }
+
+    pub(super) fn get(&self, id: &UnitId) -> Option<JobKind> {
+        self.jobs.get(id).copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use super::{JobKind, Transaction, TransactionError};
+    use crate::{
+        actor::dep::{FullDepInfo, JobWaitInfo},
+        unit::{JobMode, State, UnitId},
+    };
+
+    fn ids(names: &[&str]) -> HashSet<UnitId> {
+        names.iter().map(|&n| UnitId::from(n)).collect()
+    }
+
+    /// a requires b, wants c; c conflicts d; e requires a
+    fn dep_map() -> HashMap<UnitId, FullDepInfo> {
+        let mut map: HashMap<UnitId, FullDepInfo> = HashMap::new();
+        map.entry("a".into()).or_default().requires = ids(&["b"]);
+        map.entry("a".into()).or_default().wants = ids(&["c"]);
+        map.entry("a".into()).or_default().required_by = ids(&["e"]);
+        map.entry("b".into()).or_default().required_by = ids(&["a"]);
+        map.entry("c".into()).or_default().conflicts = ids(&["d"]);
+        map.entry("d".into()).or_default().conflicts = ids(&["c"]);
+        map.entry("e".into()).or_default().requires = ids(&["a"]);
+        map
+    }
+
+    /// build the transaction with no unit running and no pending job
+    fn new(
+        map: &HashMap<UnitId, FullDepInfo>,
+        loaded: &HashSet<UnitId>,
+        id: &str,
+        kind: JobKind,
+        mode: JobMode,
+    ) -> Result<Transaction, TransactionError> {
+        let (states, pending) = (HashMap::new(), HashMap::new());
+        Transaction::new(map, loaded, &states, &pending, id.into(), kind, mode)
+    }
+
+    /// `+unit` to start, `-unit` to stop
+    fn jobs(t: &Transaction) -> Vec<String> {
+        t.jobs
+            .iter()
+            .map(|(id, kind)| match kind {
+                JobKind::Start => format!("+{}", id),
+                JobKind::Stop => format!("-{}", id),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_transaction() {
+        use JobKind::{Start, Stop};
+
+        let map = dep_map();
+        let loaded = ids(&["a", "b", "c", "d", "e"]);
+        let t = new(&map, &loaded, "a", Start, JobMode::Replace).unwrap();
+        assert_eq!(jobs(&t), ["+a", "+b", "+c", "-d"]);
+        let t = new(&map, &loaded, "b", Stop, JobMode::Fail).unwrap();
+        assert_eq!(jobs(&t), ["-a", "-b", "-e"]);
+        let t = new(&map, &loaded, "a", Start, JobMode::IgnoreRequirements).unwrap();
+        assert_eq!(jobs(&t), ["+a"]);
+
+        // d conflicts the c pulled in
+        let mut map = dep_map();
+        map.entry("a".into()).or_default().wants = ids(&["c", "d"]);
+        assert_eq!(
+            new(&map, &loaded, "a", Start, JobMode::Replace).unwrap_err(),
+            TransactionError::Conflict("d".into())
+        );
+
+        // the missing wants are skipped, but not the requires
+        let loaded = ids(&["a", "c", "d", "e"]);
+        assert_eq!(
+            new(&map, &loaded, "a", Start, JobMode::Replace).unwrap_err(),
+            TransactionError::NotLoaded("b".into())
+        );
+        assert_eq!(
+            new(&map, &loaded, "a", Stop, JobMode::Isolate).unwrap_err(),
+            TransactionError::IsolateStop("a".into())
+        );
+    }
+
+    #[test]
+    fn test_ordering_cycle() {
+        use JobKind::{Start, Stop};
+
+        let loaded = ids(&["a", "b", "c", "d", "e"]);
+        let cycle = || vec![UnitId::from("a"), UnitId::from("b")];
+
+        // a after c after b after a, the c wanted by a is dropped along with stopping d
+        let mut map = dep_map();
+        map.entry("a".into()).or_default().after = ids(&["c"]);
+        map.entry("c".into()).or_default().after = ids(&["b"]);
+        map.entry("b".into()).or_default().after = ids(&["a"]);
+        let t = new(&map, &loaded, "a", Start, JobMode::Replace).unwrap();
+        assert_eq!(jobs(&t), ["+a", "+b"]);
+        // ordering is not checked at all
+        let t = new(&map, &loaded, "a", Start, JobMode::IgnoreDependencies).unwrap();
+        assert_eq!(jobs(&t), ["+a"]);
+
+        // a after b after a, both required
+        let mut map = dep_map();
+        map.entry("a".into()).or_default().after = ids(&["b"]);
+        map.entry("b".into()).or_default().after = ids(&["a"]);
+        assert_eq!(
+            new(&map, &loaded, "a", Start, JobMode::Replace).unwrap_err(),
+            TransactionError::OrderingCycle(cycle())
+        );
+        // stop jobs only wait on befores
+        new(&map, &loaded, "b", Stop, JobMode::Replace).unwrap();
+        map.entry("a".into()).or_default().before = ids(&["b"]);
+        map.entry("b".into()).or_default().before = ids(&["a"]);
+        assert_eq!(
+            new(&map, &loaded, "b", Stop, JobMode::Replace).unwrap_err(),
+            TransactionError::OrderingCycle(cycle())
+        );
+        assert_eq!(
+            TransactionError::OrderingCycle(cycle()).to_string(),
+            "ordering cycle a -> b -> a"
+        );
+    }
+
+    #[test]
+    fn test_pending_cycle() {
+        use JobKind::Start;
+
+        let loaded = ids(&["a", "b", "c", "d", "e", "x"]);
+        let states = HashMap::new();
+        let mut pending = HashMap::new();
+        let start = |after: &[&str]| JobWaitInfo::Start { after: ids(after) };
+
+        // a after b, the pending start of b the new one merges into waits on a
+        let mut map = dep_map();
+        map.entry("a".into()).or_default().after = ids(&["b"]);
+        pending.insert(UnitId::from("b"), start(&["a"]));
+        assert_eq!(
+            Transaction::new(
+                &map,
+                &loaded,
+                &states,
+                &pending,
+                "a".into(),
+                Start,
+                JobMode::Replace
+            )
+            .unwrap_err(),
+            TransactionError::OrderingCycle(vec!["a".into(), "b".into()])
+        );
+
+        // a after c after x, the pending start of x waits on a, the c wanted by a is dropped
+        let mut map = dep_map();
+        map.entry("a".into()).or_default().after = ids(&["c"]);
+        map.entry("c".into()).or_default().after = ids(&["x"]);
+        pending.clear();
+        pending.insert(UnitId::from("x"), start(&["a"]));
+        let t = Transaction::new(
+            &map,
+            &loaded,
+            &states,
+            &pending,
+            "a".into(),
+            Start,
+            JobMode::Replace,
+        )
+        .unwrap();
+        assert_eq!(jobs(&t), ["+a", "+b"]);
+    }
+
+    #[test]
+    fn test_requisite() {
+        use JobKind::Start;
+
+        // a requisite f
+        let mut map = dep_map();
+        map.entry("a".into()).or_default().requisite = ids(&["f"]);
+        let loaded = ids(&["a", "b", "c", "d", "e", "f"]);
+        let mut states = HashMap::new();
+        let pending = HashMap::new();
+        assert_eq!(
+            Transaction::new(
+                &map,
+                &loaded,
+                &states,
+                &pending,
+                "a".into(),
+                Start,
+                JobMode::Replace
+            )
+            .unwrap_err(),
+            TransactionError::RequisiteNotActive("a".into(), "f".into())
+        );
+        let t = Transaction::new(
+            &map,
+            &loaded,
+            &states,
+            &pending,
+            "a".into(),
+            Start,
+            JobMode::IgnoreRequirements,
+        )
+        .unwrap();
+        assert_eq!(jobs(&t), ["+a"]);
+        states.insert(UnitId::from("f"), State::Active);
+        let t = Transaction::new(
+            &map,
+            &loaded,
+            &states,
+            &pending,
+            "a".into(),
+            Start,
+            JobMode::Replace,
+        )
+        .unwrap();
+        assert_eq!(jobs(&t), ["+a", "+b", "+c", "-d"]);
+
+        // the job of c wanted by a is dropped instead
+        let mut map = dep_map();
+        map.entry("c".into()).or_default().requisite = ids(&["f"]);
+        states.insert(UnitId::from("f"), State::Failed);
+        let t = Transaction::new(
+            &map,
+            &loaded,
+            &states,
+            &pending,
+            "a".into(),
+            Start,
+            JobMode::Replace,
+        )
+        .unwrap();
+        assert_eq!(jobs(&t), ["+a", "+b"]);
+        assert_eq!(
+            TransactionError::RequisiteNotActive("a".into(), "f".into()).to_string(),
+            "requisite f of a is not active"
+        );
+    }
+
+    fn isolate(
+        map: &HashMap<UnitId, FullDepInfo>,
+        loaded: &HashSet<UnitId>,
+        states: &HashMap<UnitId, State>,
+        pending: &HashMap<UnitId, JobWaitInfo>,
+        mode: JobMode,
+    ) -> Result<Transaction, TransactionError> {
+        let id = UnitId::from("a.target");
+        Transaction::new(map, loaded, states, pending, id, JobKind::Start, mode)
+    }
+
+    #[test]
+    fn test_isolate() {
+        // a.target requires b.service
+        let mut map: HashMap<UnitId, FullDepInfo> = HashMap::new();
+        map.entry("a.target".into()).or_default().requires = ids(&["b.service"]);
+        map.entry("b.service".into()).or_default().required_by = ids(&["a.target"]);
+        let loaded = ids(&[
+            "a.target",
+            "b.service",
+            "c.service",
+            "d.service",
+            "e.service",
+            "m.mount",
+        ]);
+        let mut states = HashMap::new();
+        for (unit, state) in [
+            ("c.service", State::Active),
+            ("d.service", State::Stopped),
+            ("m.mount", State::Active),
+        ] {
+            states.insert(UnitId::from(unit), state);
+        }
+        let mut pending = HashMap::new();
+
+        // the dead units and the mounts are left alone
+        let t = isolate(&map, &loaded, &states, &pending, JobMode::Isolate).unwrap();
+        assert_eq!(jobs(&t), ["+a.target", "+b.service", "-c.service"]);
+
+        // the stop jobs are checked for cycles too
+        states.insert(UnitId::from("e.service"), State::Active);
+        map.entry("c.service".into()).or_default().before = ids(&["e.service"]);
+        map.entry("e.service".into()).or_default().before = ids(&["c.service"]);
+        assert_eq!(
+            isolate(&map, &loaded, &states, &pending, JobMode::Isolate).unwrap_err(),
+            TransactionError::OrderingCycle(vec!["c.service".into(), "e.service".into()])
+        );
+
+        // fail mode checks the pending jobs
+        pending.insert(
+            UnitId::from("b.service"),
+            JobWaitInfo::Stop {
+                before: HashSet::new(),
+            },
+        );
+        assert_eq!(
+            isolate(&map, &loaded, &states, &pending, JobMode::Fail).unwrap_err(),
+            TransactionError::JobConflict("b.service".into())
+        );
+    }
+}
@@ -1,43 +1,107 @@
-use std::collections::{hash_map::Entry, HashMap};
+use std::{
+    collections::{hash_map::Entry, BTreeSet, HashMap},
+    os::{fd::OwnedFd, unix::process::ExitStatusExt},
+    process::ExitStatus,
+};
 
 use tokio::{
     select,
     sync::{
         mpsc::{self, Receiver, Sender},
-        oneshot,
+        oneshot, watch,
     },
     task::{yield_now, JoinHandle},
 };
 
 use super::{
     dep, mount_monitor,
-    state::{self, set_state},
-    unit::{self, utils::get_unit},
+    state::{self, set_state, watch_state},
+    unit::{
+        self,
+        utils::{get_unit, start_unit, start_unit_with_env},
+    },
 };
 use crate::{
     actor::state::set_state_with_condition,
-    unit::{RtMsg, State, UnitId, UnitKind, UnitObj},
+    unit::{timer::TimerInfo, Env, Extra, JobMode, RtMsg, State, UnitId, UnitKind, UnitObj},
 };
 
-struct Extra {}
-
 pub(crate) enum GuardMessage {
-    DepsReady,
+    DepsReady(Extra),
     DepsFailed,
     Stop,
     NotifyDead,
+    /// get the listening fds of the running unit
+    ListenFds(oneshot::Sender<Vec<OwnedFd>>),
+    /// get the runtime info of the running timer
+    TimerInfo(oneshot::Sender<Option<TimerInfo>>),
+    /// apply the latest configuration in the store to the running unit
+    Reload,
+    /// take the latest static info in the store, without applying it
+    Update,
+}
+
+/// what the `on_failure`/`on_success` units get to know about the unit they monitor
+fn monitor_env(id: &UnitId, state: State, exit_status: Option<ExitStatus>) -> Env {
+    let result = match exit_status {
+        _ if state != State::Failed => "success",
+        Some(status) if status.core_dumped() => "core-dump",
+        Some(status) if status.signal().is_some() => "signal",
+        _ => "exit-code",
+    };
+    let mut env = vec![
+        ("MONITOR_UNIT", id.to_string()),
+        ("MONITOR_SERVICE_RESULT", result.to_string()),
+    ];
+    // the exit code, or the signal killing the process
+    if let Some(status) = exit_status.and_then(|s| s.code().or(s.signal())) {
+        env.push(("MONITOR_EXIT_STATUS", status.to_string()));
+    }
+    env
 }
 
 /// the guard during the lifetime of the unit
 struct Guard {
     unit: UnitObj,
-    extra: Option<Extra>,
+    /// passed with the start job creating the guard
+    env: Env,
     state: Sender<state::Message>,
+    store: Sender<unit::Message>,
 }
 
 impl Guard {
-    fn new(unit: UnitObj, extra: Option<Extra>, state: Sender<state::Message>) -> Self {
-        Self { unit, extra, state }
+    fn new(
+        unit: UnitObj,
+        env: Env,
+        state: Sender<state::Message>,
+        store: Sender<unit::Message>,
+    ) -> Self {
+        Self {
+            unit,
+            env,
+            state,
+            store,
+        }
+    }
+
+    /// take the latest static info in the store
+    async fn update(&mut self) {
+        if let Some(unit) = get_unit(&self.store, UnitId::from(self.unit.as_ref())).await {
+            self.unit = unit;
+        }
+    }
+
+    /// start the `on_failure` or `on_success` units once the final state is reached
+    async fn start_monitors(&self, id: &UnitId, state: State, exit_status: Option<ExitStatus>) {
+        let deps = self.unit.deps();
+        let (units, mode) = match state {
+            State::Failed => (&deps.on_failure, deps.on_failure_job_mode),
+            _ => (&deps.on_success, JobMode::Replace),
+        };
+        for unit in units.iter().cloned() {
+            let env = monitor_env(id, state, exit_status);
+            start_unit_with_env(&self.store, unit, mode, env).await;
+        }
     }
 
     /// state:
@@ -53,13 +117,20 @@ impl Guard {
     ///         }
     /// 3. wait & monitor the unit to exit \
     /// or wait stop sig and kill the unit by run `unit.stop`
-    fn run(self, mut rx: Receiver<GuardMessage>) -> JoinHandle<()> {
+    fn run(mut self, mut rx: Receiver<GuardMessage>) -> JoinHandle<()> {
         tokio::spawn(async move {
             let id = UnitId::from(self.unit.as_ref());
             // wait deps
-            if let Some(msg) = rx.recv().await {
+            let mut extra = Extra::default();
+            while let Some(msg) = rx.recv().await {
                 match msg {
-                    GuardMessage::DepsReady => (),
+                    GuardMessage::DepsReady(e) => {
+                        extra = Extra {
+                            env: std::mem::take(&mut self.env),
+                            ..e
+                        };
+                        break;
+                    }
                     GuardMessage::DepsFailed => {
                         set_state(&self.state, id.clone(), State::Failed).await;
                         return;
@@ -69,6 +140,16 @@ impl Guard {
                         return;
                     }
                     GuardMessage::NotifyDead => todo!(),
+                    // not started, nothing to share
+                    GuardMessage::ListenFds(s) => {
+                        s.send(Vec::new()).ok();
+                    }
+                    GuardMessage::TimerInfo(s) => {
+                        s.send(None).ok();
+                    }
+                    // the latest configuration is used when starting
+                    GuardMessage::Reload => (),
+                    GuardMessage::Update => self.update().await,
                 }
             }
 
@@ -84,42 +165,65 @@ impl Guard {
                 }
             }
 
-            let mut handle = match self.unit.start().await {
+            let mut handle = match self.unit.start(extra).await {
                 Ok(handle) => handle,
-                Err(()) => {
+                Err(e) => {
                     println!("unit start failed!");
                     set_state(&self.state, id.clone(), State::Failed).await;
+                    self.start_monitors(&id, State::Failed, e.exit_status).await;
                     return;
                 }
             };
             set_state(&self.state, id.clone(), State::Active).await;
+            // a oneshot has exited successfully by now, it is done
+            if let Some(exit_status) = handle.exit_status() {
+                self.start_monitors(&id, State::Active, Some(exit_status))
+                    .await;
+            }
 
             // started, wait stop_sig / quit
-            let state = loop {
+            // the monitors only follow the unit exiting by itself, not being stopped
+            let (state, exit_status, stopped) = loop {
                 select! {
                     msg = rx.recv() => match msg.unwrap() {
-                        GuardMessage::DepsReady | GuardMessage::DepsFailed => todo!("unreachable: log error for guard {}", id),
+                        GuardMessage::DepsReady(_) | GuardMessage::DepsFailed => todo!("unreachable: log error for guard {}", id),
                         GuardMessage::Stop => {
                             set_state(&self.state, id.clone(), State::Stopping).await;
+                            let exit_status = handle.exit_status();
                             match self.unit.stop(handle).await {
-                                Ok(()) => break State::Stopped,
+                                Ok(()) => break (State::Stopped, exit_status, true),
                                 Err(()) => todo!(),
                             }
                         },
                         GuardMessage::NotifyDead => {
-                            break State::Stopped
+                            break (State::Stopped, None, false)
+                        }
+                        GuardMessage::ListenFds(s) => {
+                            s.send(handle.listen_fds()).ok();
+                        }
+                        GuardMessage::TimerInfo(s) => {
+                            s.send(handle.timer_info()).ok();
+                        }
+                        GuardMessage::Update => self.update().await,
+                        GuardMessage::Reload => {
+                            self.update().await;
+                            if self.unit.reload(&mut handle).await.is_err() {
+                                println!("guard: failed to reload {}", id);
+                            }
                         }
                     },
                     rt_msg = handle.wait() => match rt_msg {
                         RtMsg::Yield => (),
-                        RtMsg::Exit(state) => break state,
-                        RtMsg::TriggerStart(id, extra) => {
-                            // todo: start the unit with extra rt info
-                        }
+                        RtMsg::Exit(state) => break (state, handle.exit_status(), false),
+                        // the fds will be collected by `GuardStore` when deps of the unit are ready
+                        RtMsg::TriggerStart(id) => start_unit(&self.store, id).await,
                     },
                 }
             };
             set_state(&self.state, id.clone(), state).await;
+            if !stopped {
+                self.start_monitors(&id, state, exit_status).await;
+            }
         })
     }
 }
@@ -127,8 +231,8 @@ impl Guard {
 pub(crate) enum Message {
     /// Query if guard of the specific unit exists
     Contains(UnitId, oneshot::Sender<bool>),
-    /// Insert a guard.
-    Insert(UnitId),
+    /// Insert a guard, with the env passed by the start job
+    Insert(UnitId, Env),
     /// remove a guard \
     /// usually called by self when a gurad quits
     Remove(UnitId),
@@ -141,11 +245,19 @@ pub(crate) enum Message {
     Stop(UnitId),
     /// Notify a unit that it already dead
     NotifyDead(UnitId),
+    /// get the runtime info of all the running timers
+    ListTimers(oneshot::Sender<Vec<(UnitId, TimerInfo)>>),
+    /// reload the running unit
+    Reload(UnitId),
+    /// the static info of the running unit is changed, like the options of an external mount
+    Update(UnitId),
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct GuardStore {
     map: HashMap<UnitId, Sender<GuardMessage>>,
+    /// units => the units triggering them, whose fds should be passed when starting
+    triggered_by: HashMap<UnitId, BTreeSet<UnitId>>,
     dep: Sender<dep::Message>,
     state: Sender<state::Message>,
     unit: Sender<unit::Message>,
@@ -161,6 +273,7 @@ impl GuardStore {
     ) -> Self {
         Self {
             map: Default::default(),
+            triggered_by: Default::default(),
             dep,
             state,
             unit,
@@ -183,9 +296,20 @@ impl GuardStore {
                         };
                         sender.send(ret).unwrap();
                     }
-                    Message::Insert(id) => {
+                    Message::Insert(id, env) => {
                         println!("guard: inserting {}", id);
                         let unitobj = get_unit(&self.unit, id.clone()).await.unwrap();
+                        let deps = unitobj.deps();
+                        for unit in deps.triggers.iter() {
+                            self.triggered_by
+                                .entry(unit.clone())
+                                .or_default()
+                                .insert(id.clone());
+                        }
+                        self.triggered_by
+                            .entry(id.clone())
+                            .or_default()
+                            .extend(deps.triggered_by.iter().cloned());
                         // hack for mountpoint monitor
                         if unitobj.kind() == UnitKind::Mount {
                             self.mount_monitor
@@ -196,7 +320,8 @@ impl GuardStore {
                         match self.map.entry(id.clone()) {
                             Entry::Occupied(mut o) if o.get().is_closed() => {
                                 let (sender, recevier) = mpsc::channel(4); // todo: remove magic number
-                                Guard::new(unitobj, None, self.state.clone()).run(recevier);
+                                Guard::new(unitobj, env, self.state.clone(), self.unit.clone())
+                                    .run(recevier);
                                 o.insert(sender);
                             }
                             Entry::Occupied(_) => {
@@ -205,7 +330,8 @@ impl GuardStore {
                             Entry::Vacant(v) => {
                                 // unit not running, create the guard to start the unit
                                 let (sender, recevier) = mpsc::channel(4); // todo: remove magic number
-                                Guard::new(unitobj, None, self.state.clone()).run(recevier);
+                                Guard::new(unitobj, env, self.state.clone(), self.unit.clone())
+                                    .run(recevier);
                                 v.insert(sender);
                             }
                         }
@@ -215,10 +341,15 @@ impl GuardStore {
                         self.map.remove(&id);
                     }
                     Message::DepsReady(id) => {
+                        let extra = Extra {
+                            fds: self.listen_fds(&id).await,
+                            triggers: self.watch_triggers(&id).await,
+                            env: Env::new(),
+                        };
                         self.map
                             .get(&id)
                             .unwrap()
-                            .send(GuardMessage::DepsReady)
+                            .send(GuardMessage::DepsReady(extra))
                             .await
                             .ok(); // ignore error here since guard already dropped, this is useless to send
                     }
@@ -249,10 +380,65 @@ impl GuardStore {
                             .await
                             .ok();
                     }
+                    Message::Reload(id) => match self.map.get(&id) {
+                        Some(guard) => {
+                            guard.send(GuardMessage::Reload).await.ok();
+                        }
+                        None => println!("guard: {} is not running, nothing to reload", id),
+                    },
+                    Message::Update(id) => {
+                        if let Some(guard) = self.map.get(&id) {
+                            guard.send(GuardMessage::Update).await.ok();
+                        }
+                    }
+                    Message::ListTimers(sender) => {
+                        let mut timers = Vec::new();
+                        for (id, guard) in self.map.iter() {
+                            if id.kind() != UnitKind::Timer {
+                                continue;
+                            }
+                            let (s, r) = oneshot::channel();
+                            if guard.send(GuardMessage::TimerInfo(s)).await.is_err() {
+                                continue;
+                            }
+                            if let Ok(Some(info)) = r.await {
+                                timers.push((id.clone(), info));
+                            }
+                        }
+                        sender.send(timers).ok();
+                    }
                 }
             }
         })
     }
+
+    /// collect the listening fds from the running units which trigger the unit
+    async fn listen_fds(&self, id: &UnitId) -> Vec<OwnedFd> {
+        let mut fds = Vec::new();
+        for trigger in self.triggered_by.get(id).into_iter().flatten() {
+            let Some(guard) = self.map.get(trigger) else {
+                continue;
+            };
+            let (s, r) = oneshot::channel();
+            if guard.send(GuardMessage::ListenFds(s)).await.is_ok() {
+                fds.extend(r.await.unwrap_or_default());
+            }
+        }
+        fds
+    }
+
+    /// watch the states of the units triggered by the unit
+    async fn watch_triggers(&self, id: &UnitId) -> Vec<(UnitId, watch::Receiver<State>)> {
+        let Some(unitobj) = get_unit(&self.unit, id.clone()).await else {
+            return Vec::new();
+        };
+        let mut triggers = Vec::new();
+        for unit in unitobj.deps().triggers.iter().cloned() {
+            let state = watch_state(&self.state, unit.clone()).await;
+            triggers.push((unit, state));
+        }
+        triggers
+    }
 }
 
 // pub(crate) async fn create_guard(guard_manager: &Sender<Message>, u: UnitId) {
@@ -263,8 +449,51 @@ impl GuardStore {
 //     guard_manager.send(Message::Stop(u)).await.unwrap()
 // }
 
+pub(crate) async fn list_timers(guard_manager: &Sender<Message>) -> Vec<(UnitId, TimerInfo)> {
+    let (s, r) = oneshot::channel();
+    guard_manager.send(Message::ListTimers(s)).await.unwrap();
+    r.await.unwrap()
+}
+
 pub(crate) async fn is_guard_exists(guard_manager: &Sender<Message>, u: UnitId) -> bool {
     let (s, r) = oneshot::channel();
     guard_manager.send(Message::Contains(u, s)).await.unwrap();
     r.await.unwrap()
 }
+
+#[cfg(test)]
+mod test {
+    use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+
+    use super::monitor_env;
+    use crate::unit::{State, UnitId};
+
+    #[test]
+    fn test_monitor_env() {
+        let id = UnitId::from("a.service");
+        let env = |state, status: Option<i32>| {
+            monitor_env(&id, state, status.map(ExitStatus::from_raw))
+                .into_iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            env(State::Failed, Some(3 << 8)),
+            [
+                "MONITOR_UNIT=a.service",
+                "MONITOR_SERVICE_RESULT=exit-code",
+                "MONITOR_EXIT_STATUS=3"
+            ]
+        );
+        // killed by SIGKILL
+        assert_eq!(
+            env(State::Failed, Some(9))[1..],
+            ["MONITOR_SERVICE_RESULT=signal", "MONITOR_EXIT_STATUS=9"]
+        );
+        assert_eq!(
+            env(State::Stopped, Some(0))[1..],
+            ["MONITOR_SERVICE_RESULT=success", "MONITOR_EXIT_STATUS=0"]
+        );
+        assert_eq!(env(State::Failed, None).len(), 2);
+    }
+}
@@ -1,16 +1,21 @@
 use tokio::sync::mpsc::{channel, Sender};
 
-use crate::actor::{
-    dep::DepStore, guard::GuardStore, mount_monitor::MountMonitorStore, state::StateStore,
-    unit::UnitStore,
+use crate::{
+    actor::{
+        dep::DepStore, guard::GuardStore, mount_monitor::MountMonitorStore, state::StateStore,
+        unit::UnitStore,
+    },
+    unit::{target::standard_targets, UnitId},
+    Rc,
 };
 
 pub(crate) mod dep;
 pub(crate) mod guard;
+pub(crate) mod mount_monitor;
 pub(crate) mod state;
 pub(crate) mod unit;
 
-mod mount_monitor;
+#[cfg(test)]
 mod test;
 
 pub(crate) struct Actors {
@@ -42,7 +47,14 @@ impl Actors {
         )
         .run(guard_rx);
         DepStore::new(dep.clone(), state.clone(), guard.clone()).run(dep_rx);
-        MountMonitorStore::new(guard.clone()).run(mount_monitor_rx);
+        MountMonitorStore::new(guard.clone(), unit.clone()).run(mount_monitor_rx);
+
+        // the built-in targets go first, so that the unit files can override them
+        for target in standard_targets() {
+            let id = UnitId::from(&target);
+            unit.try_send(unit::Message::Update(id, Rc::new(target)))
+                .unwrap();
+        }
 
         Self {
             store: unit,
@@ -1,54 +1,65 @@
-use std::{collections::HashSet, time::Duration};
+use std::collections::HashSet;
 
-use notify::{Config, PollWatcher, RecursiveMode, Watcher};
-use tap::Pipe;
 use tokio::{
-    fs, select,
-    sync::mpsc::{self, Receiver, Sender},
+    select,
+    sync::mpsc::{Receiver, Sender},
     task::JoinHandle,
 };
 
 use crate::{
-    unit::{Unit, UnitId},
-    util::mount::{mount_point_to_unit_name, ProcMountInfoLine},
+    unit::{mount::Impl as MountImpl, UnitId, UnitImpl},
+    util::mount::{
+        mount_point_to_unit_name, proc_file_changed, watch_proc_file, MountTable,
+        ProcMountInfoLine, PROC_MOUNTINFO,
+    },
 };
 
-use super::guard;
+use super::{
+    guard,
+    unit::{
+        self,
+        utils::{get_unit, start_unit, update_unit},
+    },
+};
 
 pub(crate) enum Message {
     Registor(UnitId),
     Remove(UnitId),
+    /// track the mounts existing now, called when the configured units are loaded
+    Sync,
 }
 
 pub(crate) struct MountMonitorStore {
     map: HashSet<UnitId>,
+    /// the last snapshot of mounts
+    table: MountTable,
+    /// units synthesised for the mounts not configured, like mounted by hand
+    external: HashSet<UnitId>,
+    /// new mounts are tracked only after `Sync`, so that the configured units are not shadowed
+    synced: bool,
     guard: Sender<guard::Message>,
+    store: Sender<unit::Message>,
+}
+
+fn mount_unit_id(mount: &ProcMountInfoLine) -> UnitId {
+    UnitId::from(mount_point_to_unit_name(&mount.mount_point).as_str())
 }
 
 impl MountMonitorStore {
-    pub(crate) fn new(guard: Sender<guard::Message>) -> Self {
+    pub(crate) fn new(guard: Sender<guard::Message>, store: Sender<unit::Message>) -> Self {
         Self {
             map: Default::default(),
+            table: Default::default(),
+            external: Default::default(),
+            synced: false,
             guard,
+            store,
         }
     }
     pub(crate) fn run(mut self, mut receiver: Receiver<Message>) -> JoinHandle<()> {
-        let (tx, mut rx) = mpsc::channel(1);
-        let mut watcher = PollWatcher::new(
-            move |res| {
-                futures::executor::block_on(async {
-                    tx.send(res).await.unwrap();
-                })
-            },
-            Config::default()
-                .with_compare_contents(true)
-                .with_poll_interval(Duration::from_secs_f64(0.5)),
-        )
-        .unwrap();
-        watcher
-            .watch("/proc/self/mountinfo".as_ref(), RecursiveMode::NonRecursive)
-            .unwrap();
+        let mountinfo = watch_proc_file(PROC_MOUNTINFO).unwrap();
         tokio::spawn(async move {
+            self.table = MountTable::read().await.unwrap_or_default();
             loop {
                 select! {
                     Some(msg) = receiver.recv() => {
@@ -59,23 +70,70 @@ impl MountMonitorStore {
                             Message::Remove(id) => {
                                 self.map.remove(&id);
                             }
+                            Message::Sync => {
+                                self.synced = true;
+                                let mounts = self.table.iter().cloned().collect();
+                                self.track(mounts).await;
+                            }
                         }
                     }
-                    Some(msg) = rx.recv() => {
-                        let _ = msg.unwrap();
-                        let mount_info = fs::read_to_string("/proc/self/mountinfo").await.unwrap();
-                        let mount_info = mount_info
-                            .lines()
-                            .map(|line| ProcMountInfoLine::parse(line).mount_point.pipe(|m| UnitId::from(mount_point_to_unit_name(&m).as_str())))
+                    res = proc_file_changed(&mountinfo) => {
+                        res.unwrap();
+                        let table = MountTable::read().await.unwrap();
+                        let diff = self.table.diff(&table);
+                        if diff.is_empty() {
+                            continue;
+                        }
+                        // a mount point is dead only when all the mounts stacked on it are gone
+                        let dead = diff
+                            .removed
+                            .iter()
+                            .filter(|m| !table.is_mounted(m.mount_point.as_ref()))
+                            .map(mount_unit_id)
                             .collect::<HashSet<_>>();
-                        let dead = self.map.difference(&mount_info).cloned().collect::<Vec<_>>();
                         for unit_id in dead {
-                            self.map.remove(&unit_id);
-                            self.guard.send(guard::Message::NotifyDead(unit_id)).await.unwrap();
+                            if self.map.remove(&unit_id) {
+                                self.guard.send(guard::Message::NotifyDead(unit_id.clone())).await.unwrap();
+                            }
+                            if self.external.remove(&unit_id) {
+                                self.store.send(unit::Message::Remove(unit_id)).await.unwrap();
+                            }
                         }
+                        if self.synced {
+                            self.track(diff.added.clone()).await;
+                            // keep the synthesised units up to date, e.g. after remounting
+                            for (_, mount) in diff.changed.iter() {
+                                let id = mount_unit_id(mount);
+                                let on_top = table.find(mount.mount_point.as_ref()) == Some(mount);
+                                if on_top && self.external.contains(&id) {
+                                    update_unit(&self.store, UnitImpl::<MountImpl>::external(mount)).await;
+                                    self.guard.send(guard::Message::Update(id)).await.unwrap();
+                                }
+                            }
+                        }
+                        self.table = table;
                     }
                 }
             }
         })
     }
+
+    /// make the units of the mounts active, synthesise them if not configured
+    async fn track(&mut self, mounts: Vec<ProcMountInfoLine>) {
+        let mut tracked = HashSet::new();
+        // the later mounts are on top of the former ones on the same mount point
+        for mount in mounts.iter().rev() {
+            let id = mount_unit_id(mount);
+            // mounted by the unit itself, or stacked on the tracked one
+            if self.map.contains(&id) || !tracked.insert(id.clone()) {
+                continue;
+            }
+            if self.external.contains(&id) || get_unit(&self.store, id.clone()).await.is_none() {
+                update_unit(&self.store, UnitImpl::<MountImpl>::external(mount)).await;
+                self.external.insert(id.clone());
+            }
+            // the unit finds the mount point mounted, and becomes active without mounting
+            start_unit(&self.store, id).await;
+        }
+    }
 }
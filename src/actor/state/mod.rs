@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use tokio::{
     sync::{
         mpsc::{Receiver, Sender},
-        oneshot,
+        oneshot, watch,
     },
     task::JoinHandle,
 };
@@ -18,6 +18,10 @@ pub(crate) enum Message {
     DbgPrint,
     /// get state of the unit
     Get(UnitId, oneshot::Sender<State>),
+    /// get the states of all the units, the missing ones are `Uninit`
+    List(oneshot::Sender<HashMap<UnitId, State>>),
+    /// get a receiver which is notified when the state of the unit changes
+    Watch(UnitId, oneshot::Sender<watch::Receiver<State>>),
     /// set state of the unit
     Set(UnitId, State),
     /// set state of the unit due to current state
@@ -32,6 +36,7 @@ pub(crate) enum Message {
 #[derive(Debug)]
 pub(crate) struct StateStore {
     state: HashMap<UnitId, State>,
+    watchers: HashMap<UnitId, watch::Sender<State>>,
     dep: Sender<dep::Message>,
 }
 
@@ -39,6 +44,7 @@ impl StateStore {
     pub(crate) fn new(dep: Sender<dep::Message>) -> Self {
         Self {
             state: Default::default(),
+            watchers: Default::default(),
             dep,
         }
     }
@@ -55,6 +61,17 @@ impl StateStore {
                             s.send(State::Uninit).ok();
                         }
                     }
+                    Message::List(s) => {
+                        s.send(self.state.clone()).ok();
+                    }
+                    Message::Watch(id, s) => {
+                        let state = self.state.get(&id).copied().unwrap_or_default();
+                        let watcher = self
+                            .watchers
+                            .entry(id)
+                            .or_insert_with(|| watch::channel(state).0);
+                        s.send(watcher.subscribe()).ok();
+                    }
                     Message::Set(id, new_state) => self.set(id, new_state).await,
                     Message::SetWithCondition {
                         id,
@@ -76,6 +93,9 @@ impl StateStore {
     async fn set(&mut self, id: UnitId, state: State) {
         println!("setting state: `{}` to `{}`", id, state);
         self.state.insert(id.clone(), state);
+        if let Some(watcher) = self.watchers.get(&id) {
+            watcher.send_replace(state);
+        }
         self.dep
             .send(dep::Message::StateChange(id, state))
             .await
@@ -89,6 +109,21 @@ pub(crate) async fn get_state(state_manager: &Sender<Message>, id: UnitId) -> St
     r.await.unwrap()
 }
 
+pub(crate) async fn list_states(state_manager: &Sender<Message>) -> HashMap<UnitId, State> {
+    let (s, r) = oneshot::channel();
+    state_manager.send(Message::List(s)).await.unwrap();
+    r.await.unwrap()
+}
+
+pub(crate) async fn watch_state(
+    state_manager: &Sender<Message>,
+    id: UnitId,
+) -> watch::Receiver<State> {
+    let (s, r) = oneshot::channel();
+    state_manager.send(Message::Watch(id, s)).await.unwrap();
+    r.await.unwrap()
+}
+
 pub(crate) async fn set_state(state_manager: &Sender<Message>, id: UnitId, state: State) {
     state_manager.send(Message::Set(id, state)).await.unwrap();
 }
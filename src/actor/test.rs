@@ -1,3 +1,6 @@
+use super::Actors;
+use crate::unit::{State, UnitId};
+
 async fn wait() {
     use std::time::Duration;
     use tokio::time::sleep;
@@ -6,6 +9,40 @@ async fn wait() {
     println!("waiting end");
 }
 
+/// wait until the state of the unit fits, fail the test if it takes too long
+async fn wait_state(actors: &Actors, name: &str, f: impl FnMut(&State) -> bool) {
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    use crate::actor::state::watch_state;
+
+    let mut watcher = watch_state(&actors.state, UnitId::from(name)).await;
+    timeout(Duration::from_secs(5), watcher.wait_for(f))
+        .await
+        .unwrap_or_else(|_| panic!("timed out waiting for the state of {}", name))
+        .unwrap();
+}
+
+/// the current state of the unit
+async fn state(actors: &Actors, name: &str) -> State {
+    crate::actor::state::get_state(&actors.state, UnitId::from(name)).await
+}
+
+/// run the test on a new runtime, with the actors and the units in `./units` loaded
+fn run_with_units(f: impl AsyncFnOnce(&Actors)) {
+    use crate::{actor::unit::utils::update_units, util::loader::load_units_from_dir};
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async {
+            let actors = Actors::new();
+            update_units(&actors.store, load_units_from_dir("./units").await).await;
+            f(&actors).await;
+        });
+}
+
 #[test]
 fn test_basic() {
     use crate::{actor::unit::utils::stop_unit, unit::UnitId, util::loader::load_units_from_dir};
@@ -90,3 +127,198 @@ fn test_conflict() {
             wait().await;
         });
 }
+
+#[test]
+fn test_isolate() {
+    use crate::actor::unit::utils::{isolate_unit, start_unit, stop_unit};
+
+    run_with_units(async |actors| {
+        start_unit(&actors.store, UnitId::from("t0.service")).await;
+        wait_state(actors, "t0.service", State::is_active).await;
+
+        // t0 is not pulled in by t1, so it should stop
+        isolate_unit(&actors.store, UnitId::from("t1.service")).await;
+        wait_state(actors, "t0.service", |&s| s == State::Stopped).await;
+        wait_state(actors, "t1.service", State::is_active).await;
+
+        isolate_unit(&actors.store, UnitId::from("t0.service")).await;
+        wait_state(actors, "t0.service", State::is_active).await;
+
+        stop_unit(&actors.store, UnitId::from("t1.service")).await;
+        wait_state(actors, "t1.service", State::is_dead).await;
+    });
+}
+
+#[test]
+fn test_extra_deps() {
+    use crate::{
+        actor::{
+            dep::transaction::{JobKind, TransactionError},
+            unit::utils::{enqueue_job, start_unit, stop_unit},
+        },
+        unit::JobMode,
+    };
+
+    run_with_units(async |actors| {
+        // requisite is never started, the request is rejected
+        assert_eq!(
+            enqueue_job(
+                &actors.store,
+                UnitId::from("requisite-short.service"),
+                JobKind::Start,
+                JobMode::Replace
+            )
+            .await,
+            Err(TransactionError::RequisiteNotActive(
+                UnitId::from("requisite-short.service"),
+                UnitId::from("short.service")
+            ))
+        );
+        assert_eq!(
+            state(actors, "requisite-short.service").await,
+            State::Uninit
+        );
+        assert_eq!(state(actors, "short.service").await, State::Uninit);
+
+        // binds_to stops once the dep exits
+        start_unit(&actors.store, UnitId::from("bound-to-short.service")).await;
+        wait_state(actors, "bound-to-short.service", State::is_active).await;
+        wait_state(actors, "bound-to-short.service", |&s| s == State::Stopped).await;
+        wait_state(actors, "short.service", |&s| s == State::Stopped).await;
+
+        // upholds starts the dep again after it exits
+        start_unit(&actors.store, UnitId::from("upholds-short.service")).await;
+        wait_state(actors, "short.service", State::is_active).await;
+        wait_state(actors, "short.service", State::is_dead).await;
+        wait_state(actors, "short.service", State::is_active).await;
+
+        stop_unit(&actors.store, UnitId::from("upholds-short.service")).await;
+        wait_state(actors, "upholds-short.service", State::is_dead).await;
+        stop_unit(&actors.store, UnitId::from("short.service")).await;
+        wait_state(actors, "short.service", State::is_dead).await;
+
+        // on_failure is started once the unit fails, with `MONITOR_EXIT_STATUS`
+        start_unit(&actors.store, UnitId::from("fail.service")).await;
+        wait_state(actors, "fail.service", |&s| s == State::Failed).await;
+        wait_state(actors, "on-failure.service", State::is_active).await;
+        stop_unit(&actors.store, UnitId::from("on-failure.service")).await;
+        wait_state(actors, "on-failure.service", State::is_dead).await;
+
+        // the same for a oneshot failing to start
+        start_unit(&actors.store, UnitId::from("fail-oneshot.service")).await;
+        wait_state(actors, "fail-oneshot.service", |&s| s == State::Failed).await;
+        wait_state(actors, "on-failure.service", State::is_active).await;
+
+        // on_success is started once a oneshot is done, with its exit status too
+        start_unit(&actors.store, UnitId::from("success-oneshot.service")).await;
+        wait_state(actors, "success-oneshot.service", State::is_active).await;
+        wait_state(actors, "on-success.service", State::is_active).await;
+    });
+}
+
+#[test]
+fn test_job_modes() {
+    use crate::{
+        actor::{
+            dep::transaction::{JobKind, TransactionError},
+            unit::utils::{enqueue_job, stop_unit},
+        },
+        unit::JobMode,
+    };
+
+    run_with_units(async |actors| {
+        let enqueue = |name: &'static str, kind, mode| {
+            enqueue_job(&actors.store, UnitId::from(name), kind, mode)
+        };
+
+        assert_eq!(
+            enqueue("missing.service", JobKind::Start, JobMode::Replace).await,
+            Err(TransactionError::NotLoaded(UnitId::from("missing.service")))
+        );
+
+        // the requires are not started
+        let first = enqueue(
+            "bound-to-short.service",
+            JobKind::Start,
+            JobMode::IgnoreRequirements,
+        )
+        .await
+        .unwrap();
+        wait_state(actors, "bound-to-short.service", State::is_active).await;
+        assert_eq!(state(actors, "short.service").await, State::Uninit);
+        stop_unit(&actors.store, UnitId::from("bound-to-short.service")).await;
+        wait_state(actors, "bound-to-short.service", State::is_dead).await;
+
+        // the start job waits for slow.service, and is not replaced in fail mode
+        let second = enqueue("after-slow.service", JobKind::Start, JobMode::Replace)
+            .await
+            .unwrap();
+        assert!(second > first);
+        assert_eq!(
+            enqueue("after-slow.service", JobKind::Stop, JobMode::Fail).await,
+            Err(TransactionError::JobConflict(UnitId::from(
+                "after-slow.service"
+            )))
+        );
+        enqueue("after-slow.service", JobKind::Stop, JobMode::Replace)
+            .await
+            .unwrap();
+        wait_state(actors, "slow.service", State::is_active).await;
+        wait_state(actors, "after-slow.service", |&s| s == State::Stopped).await;
+        stop_unit(&actors.store, UnitId::from("slow.service")).await;
+        wait_state(actors, "slow.service", State::is_dead).await;
+    });
+}
+
+#[test]
+fn test_update_deps() {
+    use crate::{
+        actor::unit::utils::{start_unit, stop_unit, update_unit},
+        unit::service::loader::load_service,
+    };
+
+    run_with_units(async |actors| {
+        let id = UnitId::from("requisite-short.service");
+
+        // the requisite dropped by the update is not checked any more
+        let unit = load_service(
+            r#"
+            name = "requisite-short.service"
+            kind = "Simple"
+            start = "sleep 10"
+            "#,
+        );
+        update_unit(&actors.store, unit).await;
+        start_unit(&actors.store, id.clone()).await;
+        wait_state(actors, "requisite-short.service", State::is_active).await;
+        assert_eq!(state(actors, "short.service").await, State::Uninit);
+
+        stop_unit(&actors.store, id).await;
+        wait_state(actors, "requisite-short.service", State::is_dead).await;
+    });
+}
+
+#[test]
+fn test_restart() {
+    use crate::actor::unit::utils::{restart_job, start_unit, stop_unit};
+
+    let dir = "part-of-sleep.service.tmp";
+    std::fs::remove_dir(dir).ok();
+    run_with_units(async |actors| {
+        start_unit(&actors.store, UnitId::from("sleep.service")).await;
+        start_unit(&actors.store, UnitId::from("part-of-sleep.service")).await;
+        wait_state(actors, "sleep.service", State::is_active).await;
+        wait_state(actors, "part-of-sleep.service", State::is_active).await;
+
+        // the unit part of it is stopped and started again, failing on the dir made by the first start
+        restart_job(&actors.store, UnitId::from("sleep.service"))
+            .await
+            .unwrap();
+        wait_state(actors, "part-of-sleep.service", |&s| s == State::Failed).await;
+        wait_state(actors, "sleep.service", State::is_active).await;
+
+        stop_unit(&actors.store, UnitId::from("sleep.service")).await;
+        wait_state(actors, "sleep.service", State::is_dead).await;
+    });
+    std::fs::remove_dir(dir).unwrap();
+}
@@ -8,8 +8,11 @@ use tokio::{
     task::JoinHandle,
 };
 
-use super::dep;
-use crate::unit::{UnitId, UnitObj};
+use super::dep::{
+    self,
+    transaction::{JobId, JobKind, TransactionError},
+};
+use crate::unit::{Env, JobMode, UnitId, UnitObj};
 
 pub(crate) mod utils;
 
@@ -22,12 +25,24 @@ pub(crate) enum Message {
     Remove(UnitId),
     /// get the static info of the unit
     Get(UnitId, oneshot::Sender<UnitObj>),
-    /// start the unit
-    Start(UnitId),
-    /// stop the unit
-    Stop(UnitId),
-    /// restart the unit
-    Restart(UnitId),
+    /// get all the units
+    List(oneshot::Sender<Vec<UnitObj>>),
+    /// start or stop the unit and its deps, replying the job id or why it is rejected \
+    /// the env is passed to the unit itself when it is started by this job
+    Enqueue(
+        UnitId,
+        JobKind,
+        JobMode,
+        Env,
+        Option<oneshot::Sender<Result<JobId, TransactionError>>>,
+    ),
+    /// stop the unit and start it again along with the units stopped with it, replying the job id
+    Restart(
+        UnitId,
+        Option<oneshot::Sender<Result<JobId, TransactionError>>>,
+    ),
+    /// reload the unit and the units it propagates reload to
+    Reload(UnitId),
 }
 
 #[derive(Debug)]
@@ -51,14 +66,11 @@ impl UnitStore {
                     Message::DbgPrint => println!("{:#?}", self.map),
                     Message::Update(id, unit) => {
                         println!("updating unit: {:?}", &id);
-                        if let Some(old) = self.map.insert(id.clone(), unit.clone()) {
-                            todo!("feature: update dep_info");
-                        } else {
-                            self.dep
-                                .send(dep::Message::Load(id, unit.deps()))
-                                .await
-                                .unwrap();
-                        }
+                        self.map.insert(id.clone(), unit.clone());
+                        self.dep
+                            .send(dep::Message::Load(id, unit.deps()))
+                            .await
+                            .unwrap();
                     }
                     Message::Remove(id) => {
                         self.map.remove(&id);
@@ -68,16 +80,27 @@ impl UnitStore {
                             sender.send(unitobj).ok();
                         }
                     }
-                    // start the unit and its deps
-                    Message::Start(id) => {
-                        println!("starting unit: {:?}", &id);
-                        self.dep.send(dep::Message::AddToStart(id)).await.unwrap()
+                    Message::List(sender) => {
+                        sender.send(self.map.values().cloned().collect()).ok();
+                    }
+                    Message::Enqueue(id, kind, mode, env, reply) => {
+                        println!("enqueuing {:?} of unit: {:?} ({:?})", kind, &id, mode);
+                        self.dep
+                            .send(dep::Message::Enqueue(id, kind, mode, env, reply))
+                            .await
+                            .unwrap()
+                    }
+                    Message::Restart(id, reply) => {
+                        println!("restarting unit: {:?}", &id);
+                        self.dep
+                            .send(dep::Message::Restart(id, reply))
+                            .await
+                            .unwrap()
                     }
-                    Message::Stop(id) => {
-                        println!("stopping unit: {:?}", &id);
-                        self.dep.send(dep::Message::AddToStop(id)).await.unwrap()
+                    Message::Reload(id) => {
+                        println!("reloading unit: {:?}", &id);
+                        self.dep.send(dep::Message::Reload(id)).await.unwrap()
                     }
-                    Message::Restart(id) => todo!(),
                 }
             }
         })
@@ -3,7 +3,8 @@ use tokio::sync::{mpsc::Sender, oneshot};
 
 use super::{Message, UnitObj};
 use crate::{
-    unit::{Unit, UnitId},
+    actor::dep::transaction::{JobId, JobKind, TransactionError},
+    unit::{Env, JobMode, Unit, UnitId},
     Rc,
 };
 
@@ -30,12 +31,78 @@ pub(crate) async fn get_unit(store: &Sender<Message>, unit_id: UnitId) -> Option
     r.await.ok()
 }
 
+pub(crate) async fn list_units(store: &Sender<Message>) -> Vec<UnitObj> {
+    let (s, r) = oneshot::channel();
+    store.send(Message::List(s)).await.unwrap();
+    r.await.unwrap_or_default()
+}
+
+/// enqueue the job and wait until it is applied or rejected
+pub(crate) async fn enqueue_job(
+    store: &Sender<Message>,
+    id: UnitId,
+    kind: JobKind,
+    mode: JobMode,
+) -> Result<JobId, TransactionError> {
+    let (s, r) = oneshot::channel();
+    store
+        .send(Message::Enqueue(id, kind, mode, Env::new(), Some(s)))
+        .await
+        .unwrap();
+    r.await.unwrap()
+}
+
+/// restart the unit and wait until the job is applied or rejected
+pub(crate) async fn restart_job(
+    store: &Sender<Message>,
+    id: UnitId,
+) -> Result<JobId, TransactionError> {
+    let (s, r) = oneshot::channel();
+    store.send(Message::Restart(id, Some(s))).await.unwrap();
+    r.await.unwrap()
+}
+
+/// enqueue the job without waiting, the error is only logged
+pub(crate) async fn enqueue_unit(
+    store: &Sender<Message>,
+    id: UnitId,
+    kind: JobKind,
+    mode: JobMode,
+) {
+    store
+        .send(Message::Enqueue(id, kind, mode, Env::new(), None))
+        .await
+        .unwrap();
+}
+
+/// start the unit with the env, like the `MONITOR_*` of the `on_failure` units
+pub(crate) async fn start_unit_with_env(
+    store: &Sender<Message>,
+    id: UnitId,
+    mode: JobMode,
+    env: Env,
+) {
+    store
+        .send(Message::Enqueue(id, JobKind::Start, mode, env, None))
+        .await
+        .unwrap();
+}
+
 pub(crate) async fn start_unit(store: &Sender<Message>, id: UnitId) {
-    store.send(Message::Start(id)).await.unwrap();
+    enqueue_unit(store, id, JobKind::Start, JobMode::Replace).await
 }
 
+#[cfg(test)]
 pub(crate) async fn stop_unit(store: &Sender<Message>, id: UnitId) {
-    store.send(Message::Stop(id)).await.unwrap();
+    enqueue_unit(store, id, JobKind::Stop, JobMode::Replace).await
+}
+
+pub(crate) async fn isolate_unit(store: &Sender<Message>, id: UnitId) {
+    enqueue_unit(store, id, JobKind::Start, JobMode::Isolate).await
+}
+
+pub(crate) async fn reload_unit(store: &Sender<Message>, id: UnitId) {
+    store.send(Message::Reload(id)).await.unwrap();
 }
 
 pub(crate) async fn print_store(store: &Sender<Message>) {
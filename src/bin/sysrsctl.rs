@@ -1,5 +1,8 @@
+use std::sync::Arc;
+
+use chrono::{Local, TimeZone};
 use clap::{Parser, Subcommand};
-use zbus::blocking::Connection;
+use zbus::{blocking::Connection, Message};
 
 #[derive(Parser, Debug)]
 struct Cli {
@@ -9,9 +12,93 @@ struct Cli {
 
 #[derive(Subcommand, Clone, Debug)]
 enum Command {
-    Start { unit: String },
-    Stop { unit: String },
+    Start {
+        unit: String,
+        /// how to treat the pending jobs: replace, fail, isolate, ignore-dependencies, ignore-requirements
+        #[arg(long, default_value = "replace")]
+        job_mode: String,
+    },
+    Stop {
+        unit: String,
+        #[arg(long, default_value = "replace")]
+        job_mode: String,
+    },
+    /// stop the unit and start it again, along with the units stopped with it
     Restart { unit: String },
+    /// start the unit and stop all the others not pulled in by it
+    Isolate { unit: String },
+    /// apply the configuration to the running unit, like remounting a mount
+    Reload { unit: String },
+    /// show the next and last elapse of the running timers
+    ListTimers,
+    /// show the loaded units and their states
+    ListUnits {
+        /// only show the units of the kind, like `mount`, `service`
+        #[arg(long = "type")]
+        kind: Option<String>,
+    },
+    /// check the configuration, like the lines of fstab
+    Verify,
+}
+
+/// format usec since epoch, 0 for not available
+fn format_usec(usec: u64) -> String {
+    match Local.timestamp_micros(usec as i64).single() {
+        Some(t) if usec != 0 => t.format("%a %Y-%m-%d %H:%M:%S %Z").to_string(),
+        _ => "n/a".to_owned(),
+    }
+}
+
+fn print_timers(timers: &[(String, u64, u64, String)]) {
+    let mut timers = timers.to_vec();
+    // like systemd, the timer elapsing first goes first
+    timers.sort_by_key(|&(_, next, _, _)| if next == 0 { u64::MAX } else { next });
+    println!("{:<32} {:<32} {:<24} ACTIVATES", "NEXT", "LAST", "UNIT");
+    for (timer, next, last, unit) in &timers {
+        println!(
+            "{:<32} {:<32} {:<24} {}",
+            format_usec(*next),
+            format_usec(*last),
+            timer,
+            unit
+        );
+    }
+    println!("\n{} timers listed.", timers.len());
+}
+
+fn print_units(units: &[(String, String, String)], kind: Option<&str>) {
+    let mut units: Vec<_> = units
+        .iter()
+        .filter(|(unit, _, _)| {
+            kind.is_none_or(|kind| unit.rsplit_once('.').is_some_and(|(_, k)| k == kind))
+        })
+        .collect();
+    units.sort();
+    println!("{:<40} {:<10} DESCRIPTION", "UNIT", "STATE");
+    for (unit, state, description) in &units {
+        println!("{:<40} {:<10} {}", unit, state, description);
+    }
+    println!("\n{} units listed.", units.len());
+}
+
+fn print_errors(errors: &[String]) {
+    for e in errors {
+        println!("{}", e);
+    }
+    if !errors.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// the id of the job enqueued, or why it is rejected
+fn print_job(reply: zbus::Result<Arc<Message>>) {
+    match reply {
+        Ok(m) => println!("job {}", m.body::<u64>().unwrap()),
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
 fn main() {
@@ -20,14 +107,46 @@ fn main() {
     let dest = Some("org.sysrs.sysrs1");
     let path = "/org/sysrs/sysrs1";
     let iface = Some("org.sysrs.sysrs1");
-    let m = match args.command {
-        Command::Start { unit } => conn.call_method(dest, path, iface, "StartUnit", &unit),
-        Command::Stop { unit } => conn.call_method(dest, path, iface, "StopUnit", &unit),
-        Command::Restart { unit } => conn.call_method(dest, path, iface, "RestartUnit", &unit),
+    match args.command {
+        Command::Start { unit, job_mode } => {
+            print_job(conn.call_method(dest, path, iface, "StartUnit", &(unit, job_mode)))
+        }
+        Command::Stop { unit, job_mode } => {
+            print_job(conn.call_method(dest, path, iface, "StopUnit", &(unit, job_mode)))
+        }
+        Command::Isolate { unit } => {
+            print_job(conn.call_method(dest, path, iface, "IsolateUnit", &unit))
+        }
+        Command::Restart { unit } => {
+            print_job(conn.call_method(dest, path, iface, "RestartUnit", &unit))
+        }
+        Command::Reload { unit } => {
+            // no reply body, only the error if any
+            if let Err(e) = conn.call_method(dest, path, iface, "ReloadUnit", &unit) {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::ListTimers => {
+            let m = conn
+                .call_method(dest, path, iface, "ListTimers", &())
+                .unwrap();
+            print_timers(&m.body::<Vec<(String, u64, u64, String)>>().unwrap());
+        }
+        Command::ListUnits { kind } => {
+            let m = conn
+                .call_method(dest, path, iface, "ListUnits", &())
+                .unwrap();
+            print_units(
+                &m.body::<Vec<(String, String, String)>>().unwrap(),
+                kind.as_deref(),
+            );
+        }
+        Command::Verify => {
+            let m = conn.call_method(dest, path, iface, "Verify", &()).unwrap();
+            print_errors(&m.body::<Vec<String>>().unwrap());
+        }
     }
-    .unwrap();
-    let reply: u8 = m.body().unwrap();
-    println!("{reply}");
     // let _ = conn.call_method(
     //     Some("org.sysrs.sysrs1"),
     //     "/org/sysrs/sysrs1",
@@ -1,12 +1,19 @@
-use crate::Rc;
+use crate::{util::mount::unescape, Rc};
 use futures::{future::ready, Stream, StreamExt};
 use std::{
+    fmt::Display,
+    io,
     num::ParseIntError,
     path::{Path, PathBuf},
 };
-use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tokio::{
+    fs::File,
+    io::{AsyncBufRead, AsyncBufReadExt, BufReader},
+};
 use tokio_stream::wrappers::LinesStream;
 
+pub(crate) const FSTAB: &str = "/etc/fstab";
+
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) struct MountInfo {
     pub(crate) fs_spec: Box<Path>,
@@ -22,31 +29,187 @@ pub(crate) struct FsEntry {
     pub fsck_order: u8,
 }
 
+/// the fields of a line, with the comment stripped and the octal escapes like `\040` decoded
+fn fields(line: &str) -> Vec<Rc<str>> {
+    line.split_ascii_whitespace()
+        .take_while(|field| !field.starts_with('#'))
+        .map(unescape)
+        .collect()
+}
+
 impl FsEntry {
-    pub fn from_buf_reader(reader: impl AsyncBufRead) -> impl Stream<Item = Self> {
+    /// entries of the lines, or the errors with the line numbers in `path`
+    pub fn from_buf_reader<'a>(
+        reader: impl AsyncBufRead + 'a,
+        path: &'a Path,
+    ) -> impl Stream<Item = Result<Self, FstabError>> + 'a {
         LinesStream::new(reader.lines())
-            .filter_map(|line| ready(line.ok()))
-            .filter(|line| ready(!line.starts_with('#')))
-            .filter(|line| ready(!line.trim().is_empty()))
-            .filter_map(|line| {
-                ready(match line.as_str().try_into() {
-                    Ok(f) => Some(f),
-                    Err(e) => {
-                        eprintln!(
-                            "warnning: line `{line}` got parse error, ignoring... error: {e:?}"
-                        );
-                        None
-                    }
+            .enumerate()
+            .filter_map(move |(i, line)| {
+                ready(match line {
+                    Ok(line) => Self::parse_line(path, i + 1, &line),
+                    Err(e) => Some(Err(FstabError::new(path, i + 1, e.into()))),
                 })
             })
     }
+
+    /// the entry of the line numbered `n` in `path`, `None` for the empty and comment lines
+    fn parse_line(path: &Path, n: usize, line: &str) -> Option<Result<Self, FstabError>> {
+        let fields = fields(line);
+        if fields.is_empty() {
+            return None;
+        }
+        let fields: Vec<&str> = fields.iter().map(|f| f.as_ref()).collect();
+        Some(Self::try_from(fields.as_slice()).map_err(|e| FstabError::new(path, n, e)))
+    }
+
+    /// all the valid entries and the errors of the fstab file
+    pub(crate) async fn read(path: &Path) -> io::Result<(Vec<Self>, Vec<FstabError>)> {
+        let file = File::open(path).await?;
+        let mut entries = Vec::new();
+        let mut errors = Vec::new();
+        let mut stream = std::pin::pin!(Self::from_buf_reader(BufReader::new(file), path));
+        while let Some(entry) = stream.next().await {
+            match entry {
+                Ok(entry) => entries.push(entry),
+                Err(e) => errors.push(e),
+            }
+        }
+        Ok((entries, errors))
+    }
 }
 
 #[derive(Debug)]
 pub enum Error {
+    Io(io::Error),
     Parse(ParseIntError),
     Argnum(usize),
-    // PathNotAbsolute(Rc<Path>),
+    /// `TAG=` with an unknown tag
+    UnknownTag(Box<str>),
+    /// `TAG=` without value
+    EmptyTag(Box<str>),
+    /// a device path which is not absolute, for a non pseudo filesystem
+    PathNotAbsolute(Box<Path>),
+    MountPointNotAbsolute(Box<Path>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Parse(e) => write!(f, "{}", e),
+            Error::Argnum(n) => write!(f, "expect 4 to 6 fields, got {}", n),
+            Error::UnknownTag(tag) => write!(f, "unknown tag `{}`", tag),
+            Error::EmptyTag(tag) => write!(f, "empty value for tag `{}`", tag),
+            Error::PathNotAbsolute(p) => write!(f, "device path {:?} is not absolute", p),
+            Error::MountPointNotAbsolute(p) => write!(f, "mount point {:?} is not absolute", p),
+        }
+    }
+}
+
+/// an error of the line in the fstab file
+#[derive(Debug)]
+pub(crate) struct FstabError {
+    pub path: Box<Path>,
+    /// starts from 1
+    pub line: usize,
+    pub error: Error,
+}
+
+impl FstabError {
+    fn new(path: &Path, line: usize, error: Error) -> Self {
+        Self {
+            path: path.into(),
+            line,
+            error,
+        }
+    }
+}
+
+impl Display for FstabError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.path.display(), self.line, self.error)
+    }
+}
+
+/// filesystems not backed by a device, whose `fs_spec` is arbitrary
+const PSEUDO_FS: &[&str] = &[
+    "autofs",
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "debugfs",
+    "devpts",
+    "devtmpfs",
+    "efivarfs",
+    "fusectl",
+    "hugetlbfs",
+    "mqueue",
+    "overlay",
+    "proc",
+    "pstore",
+    "ramfs",
+    "securityfs",
+    "sysfs",
+    "tmpfs",
+    "tracefs",
+];
+
+pub(crate) fn is_pseudo_fs(vfs_type: &str) -> bool {
+    PSEUDO_FS.contains(&vfs_type)
+}
+
+/// network filesystems, like `server:/export` for nfs, `//server/share` for cifs
+pub(crate) fn is_network_spec(fs_spec: &str) -> bool {
+    fs_spec.starts_with("//") || (!fs_spec.starts_with('/') && fs_spec.contains(':'))
+}
+
+/// escape the chars not allowed in the `/dev/disk/by-*` links, like udev does
+fn encode_devnode_name(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() || "#+-.:=@_".contains(c) || !c.is_ascii() {
+            encoded.push(c);
+        } else {
+            encoded.push_str(&format!("\\x{:02x}", c as u8));
+        }
+    }
+    encoded
+}
+
+/// resolve `TAG=value` to the link in `/dev/disk`, see `fstab(5)`
+fn resolve_fs_spec(fs_spec: &str) -> Result<PathBuf, Error> {
+    // a path may contain `=` too
+    let Some((tag, value)) = fs_spec
+        .split_once('=')
+        .filter(|_| !fs_spec.starts_with('/'))
+    else {
+        return Ok(fs_spec.into());
+    };
+    let dir = match tag {
+        "UUID" => "by-uuid",
+        "LABEL" => "by-label",
+        "PARTUUID" => "by-partuuid",
+        "PARTLABEL" => "by-partlabel",
+        "ID" => "by-id",
+        _ => return Err(Error::UnknownTag(tag.into())),
+    };
+    let value = value.trim_matches('"');
+    if value.is_empty() {
+        return Err(Error::EmptyTag(tag.into()));
+    }
+    // the links keep the case reported by blkid, like systemd's `fstab_node_to_udev_node`
+    Ok(Path::new("/dev/disk")
+        .join(dir)
+        .join(encode_devnode_name(value)))
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
 }
 
 impl From<ParseIntError> for Error {
@@ -65,32 +228,29 @@ impl TryFrom<&[&str; 4]> for FsEntry {
     type Error = Error;
 
     fn try_from(value: &[&str; 4]) -> Result<Self, Self::Error> {
-        let fs_spec = value[0];
-        let fs_spec: PathBuf = if fs_spec.starts_with("UUID") {
-            let uuid = &fs_spec["UUID".len() + 1..];
-            String::from("/dev/disk/by-uuid/") + uuid
-        } else {
-            fs_spec.to_owned()
+        let vfs_type = value[2];
+        let fs_spec = resolve_fs_spec(value[0])?;
+        // `tmpfs`, `proc`, `none`... for pseudo filesystems
+        if !fs_spec.is_absolute()
+            && !is_pseudo_fs(vfs_type)
+            && fs_spec.as_os_str() != "none"
+            && !is_network_spec(value[0])
+        {
+            return Err(Error::PathNotAbsolute(fs_spec.into()));
         }
-        .into();
         let fs_spec = fs_spec.into();
 
-        let mount_point = PathBuf::from(value[1]).into();
-
-        // check path absolute
-        // consider `tmpfs`
-        // if !fs_spec.is_absolute() {
-        //     return Err(Error::PathNotAbsolute(fs_spec));
-        // }
-        // consider `none` for swap
-        // if !mount_point.is_absolute() {
-        //     return Err(Error::PathNotAbsolute(mount_point));
-        // }
+        let mount_point = PathBuf::from(value[1]);
+        // `none` for swap
+        if !mount_point.is_absolute() && (vfs_type != "swap" || value[1] != "none") {
+            return Err(Error::MountPointNotAbsolute(mount_point.into()));
+        }
+        let mount_point = mount_point.into();
 
         let mount_info = Rc::new(MountInfo {
             fs_spec,
             mount_point,
-            vfs_type: value[2].into(),
+            vfs_type: vfs_type.into(),
             mount_options: value[3].into(),
         });
 
@@ -102,47 +262,140 @@ impl TryFrom<&[&str; 4]> for FsEntry {
     }
 }
 
-impl TryFrom<&[&str; 6]> for FsEntry {
+/// `dump` and `passno` are optional, default to 0
+impl TryFrom<&[&str]> for FsEntry {
     type Error = Error;
 
-    fn try_from(value: &[&str; 6]) -> Result<Self, Self::Error> {
-        let r: &[&str; 4] = &value[0..4].try_into().unwrap();
+    fn try_from(value: &[&str]) -> Result<Self, Self::Error> {
+        if !(4..=6).contains(&value.len()) {
+            return Err(Error::Argnum(value.len()));
+        }
+        let r: &[&str; 4] = value[0..4].try_into().unwrap();
         let r = r.try_into()?;
 
         Ok(Self {
-            dump: value[4].parse().map(|i: u8| i != 0)?,
-            fsck_order: value[5].parse()?,
+            dump: match value.get(4) {
+                Some(dump) => dump.parse().map(|i: u8| i != 0)?,
+                None => false,
+            },
+            fsck_order: match value.get(5) {
+                Some(passno) => passno.parse()?,
+                None => 0,
+            },
             ..r
         })
     }
 }
 
-impl TryFrom<&[&str]> for FsEntry {
+impl TryFrom<&str> for FsEntry {
     type Error = Error;
 
-    fn try_from(value: &[&str]) -> Result<Self, Self::Error> {
-        match value.len() {
-            4 => {
-                let v: &[&str; 4] = value.try_into().unwrap();
-                v.try_into()
-            }
-            6 => {
-                let v: &[&str; 6] = value.try_into().unwrap();
-                v.try_into()
-            }
-            len => Err(Error::Argnum(len)),
-        }
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        // get the first line
+        let value = fields(value.lines().next().unwrap_or_default());
+        let value: Vec<&str> = value.iter().map(|f| f.as_ref()).collect();
+        value.as_slice().try_into()
     }
 }
 
-impl TryFrom<&str> for FsEntry {
-    type Error = Error;
+#[cfg(test)]
+mod test {
+    use std::path::Path;
 
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        // get the first line
-        let value = value.lines().next().unwrap().trim();
+    use super::{Error, FsEntry};
 
-        let value: Vec<&str> = value.split_ascii_whitespace().collect();
-        value.as_slice().try_into()
+    fn fs_spec(line: &str) -> Result<Box<Path>, Error> {
+        FsEntry::try_from(line).map(|e| e.mount_info.fs_spec.clone())
+    }
+
+    #[test]
+    fn test_fs_spec() {
+        let spec = |s: &str| fs_spec(&format!("{} /mnt ext4 defaults 0 2", s)).unwrap();
+        assert_eq!(
+            &*spec("UUID=ABCD-1234"),
+            Path::new("/dev/disk/by-uuid/ABCD-1234")
+        );
+        assert_eq!(
+            &*spec("LABEL=\"boot/efi\""),
+            Path::new("/dev/disk/by-label/boot\\x2fefi")
+        );
+        assert_eq!(
+            &*spec("PARTLABEL=root"),
+            Path::new("/dev/disk/by-partlabel/root")
+        );
+        assert_eq!(
+            &*spec("PARTUUID=0a1b-02"),
+            Path::new("/dev/disk/by-partuuid/0a1b-02")
+        );
+        assert_eq!(
+            &*spec("ID=ata-disk-part1"),
+            Path::new("/dev/disk/by-id/ata-disk-part1")
+        );
+        assert_eq!(&*spec("/dev/sda1"), Path::new("/dev/sda1"));
+        assert_eq!(&*spec("/dev/foo=bar"), Path::new("/dev/foo=bar"));
+        assert!(fs_spec("tmpfs /tmp tmpfs defaults 0 0").is_ok());
+        assert!(fs_spec("none /proc proc defaults 0 0").is_ok());
+        assert!(fs_spec("server:/export /mnt nfs defaults 0 0").is_ok());
+        assert!(fs_spec("/dev/sda2 none swap sw 0 0").is_ok());
+    }
+
+    #[test]
+    fn test_fs_spec_errors() {
+        assert!(matches!(
+            fs_spec("FOO=bar /mnt ext4 defaults"),
+            Err(Error::UnknownTag(_))
+        ));
+        assert!(matches!(
+            fs_spec("UUID= /mnt ext4 defaults"),
+            Err(Error::EmptyTag(_))
+        ));
+        assert!(matches!(
+            fs_spec("sda1 /mnt ext4 defaults"),
+            Err(Error::PathNotAbsolute(_))
+        ));
+        assert!(matches!(
+            fs_spec("/dev/sda1 mnt ext4 defaults"),
+            Err(Error::MountPointNotAbsolute(_))
+        ));
+    }
+
+    #[test]
+    fn test_fields() {
+        let entry =
+            FsEntry::try_from("/dev/sdb1\t/mnt/my\\040data  ext4 ro 1 # data disk").unwrap();
+        assert_eq!(&*entry.mount_info.mount_point, Path::new("/mnt/my data"));
+        assert_eq!(&*entry.mount_info.mount_options, "ro");
+        assert!(entry.dump);
+        assert_eq!(entry.fsck_order, 0);
+        assert!(matches!(
+            FsEntry::try_from("/dev/sdb1 /mnt ext4 ro 0 0 0"),
+            Err(Error::Argnum(7))
+        ));
+    }
+
+    #[test]
+    fn test_line_errors() {
+        let fstab = "\
+# comment
+/dev/sda1 / ext4 defaults 0 1
+
+sda2 /home ext4 defaults 0 2
+/dev/sda3 /srv ext4 defaults 0 x
+";
+        let path = Path::new("/etc/fstab");
+        let results: Vec<_> = fstab
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| FsEntry::parse_line(path, i + 1, line))
+            .collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok());
+        let errors: Vec<String> = results
+            .iter()
+            .filter_map(|r| r.as_ref().err())
+            .map(ToString::to_string)
+            .collect();
+        assert!(errors[0].starts_with("/etc/fstab:4: "));
+        assert!(errors[1].starts_with("/etc/fstab:5: "));
     }
 }
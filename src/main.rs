@@ -1,12 +1,27 @@
-use rustix::process;
-use tokio::task::yield_now;
+use std::time::Duration;
+
+use rustix::{fs::sync, process};
+use tokio::{
+    select,
+    signal::unix::{signal, SignalKind},
+    time::{sleep, timeout},
+};
 
 use crate::{
-    actor::{unit::utils::update_units, Actors},
+    actor::{
+        dep::{ignore_on_isolate, transaction::JobKind},
+        mount_monitor,
+        state::get_state,
+        unit::utils::{enqueue_job, get_unit, isolate_unit, list_units, update_units},
+        Actors,
+    },
+    fstab::FSTAB,
+    unit::{target::SHUTDOWN_TARGET, JobMode, UnitId},
     util::{
         dbus::{connect_dbus, DbusServer},
         event::register_sig_handlers,
-        loader::load_units_from_dir,
+        loader::{load_units_from_dir, load_units_from_fstab, read_default_target},
+        mount::unmount_all,
     },
 };
 
@@ -18,6 +33,16 @@ mod fstab;
 mod unit;
 mod util;
 
+/// how long to wait for the units to stop at shutdown, same as systemd's `DefaultTimeoutStopSec=`
+const STOP_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// what to do once everything is stopped and unmounted
+#[derive(Debug, Clone, Copy)]
+enum Shutdown {
+    PowerOff,
+    Reboot,
+}
+
 fn main() {
     println!("Hello, world!");
     let uid = process::getuid();
@@ -33,34 +58,110 @@ fn main() {
         .enable_all()
         .build()
         .unwrap();
-    runtime.block_on(async_main());
+    let shutdown = runtime.block_on(async_main());
+    // nothing is using the file systems after the runtime is gone
+    drop(runtime);
+    if process::getpid().is_init() {
+        println!("unmounting file systems ...");
+        unmount_all();
+        // init must never exit, or the kernel panics
+        reboot(shutdown);
+    }
     println!("exiting...");
 }
 
-async fn async_main() {
+fn reboot(shutdown: Shutdown) -> ! {
+    sync();
+    let cmd = match shutdown {
+        Shutdown::PowerOff => libc::RB_POWER_OFF,
+        Shutdown::Reboot => libc::RB_AUTOBOOT,
+    };
+    println!("{:?} ...", shutdown);
+    // safety: no pointer is passed
+    if unsafe { libc::reboot(cmd) } != 0 {
+        println!(
+            "failed to {:?}: {}",
+            shutdown,
+            std::io::Error::last_os_error()
+        );
+    }
+    loop {
+        std::thread::park();
+    }
+}
+
+/// isolate `shutdown.target`, and wait for the units to stop before unmounting the file systems
+async fn stop_units(actors: &Actors) {
+    let shutdown = UnitId::from(SHUTDOWN_TARGET);
+    let job = enqueue_job(
+        &actors.store,
+        shutdown.clone(),
+        JobKind::Start,
+        JobMode::Isolate,
+    );
+    if let Err(e) = job.await {
+        println!("failed to stop the units: {}", e);
+        return;
+    }
+    let stopped = async {
+        'wait: loop {
+            for unit in list_units(&actors.store).await {
+                let id = UnitId::from(unit.as_ref());
+                if id == shutdown || ignore_on_isolate(&id) {
+                    continue;
+                }
+                if !get_state(&actors.state, id).await.is_dead() {
+                    sleep(Duration::from_millis(100)).await;
+                    continue 'wait;
+                }
+            }
+            break;
+        }
+    };
+    if timeout(STOP_TIMEOUT, stopped).await.is_err() {
+        println!("timed out stopping the units");
+    }
+}
+
+async fn async_main() -> Shutdown {
     println!("tokio started!");
     let actors = Actors::new();
     register_sig_handlers(&actors);
-    // println!("parsing fstab:");
-    // let path = PathBuf::from("/etc/fstab");
-    // let file = BufReader::new(OpenOptions::new().read(true).open(&path).await.unwrap());
-    // fstab::FsEntry::from_buf_reader(file)
-    //     .map(|fs| -> Box<UnitImpl<MountImpl>> { Box::new(fs.into()) })
-    //     .for_each(|mount| {
-    //         store.insert(mount);
-    //         ready(())
-    //     })
-    //     .await;
-    // dbg!(store);
 
     println!("loading units ...");
     update_units(&actors.store, load_units_from_dir("./units").await).await;
+    println!("generating mount units from fstab ...");
+    update_units(&actors.store, load_units_from_fstab(FSTAB).await).await;
     println!("units loaded!");
-    let _conn = connect_dbus(DbusServer::new(actors.store.clone(), actors.state.clone()))
+    println!("tracking existing mounts ...");
+    actors
+        .mount_monitor
+        .send(mount_monitor::Message::Sync)
         .await
         .unwrap();
-    loop {
-        // todo: handle actor failure
-        yield_now().await;
+    let target = read_default_target().await;
+    if get_unit(&actors.store, target.clone()).await.is_some() {
+        println!("starting {} ...", target);
+        isolate_unit(&actors.store, target).await;
+    } else {
+        println!("{} not found, nothing to start", target);
     }
+    let _conn = connect_dbus(DbusServer::new(
+        actors.store.clone(),
+        actors.state.clone(),
+        actors.guard.clone(),
+    ))
+    .await
+    .unwrap();
+    // todo: handle actor failure
+    // SIGINT for ctrl-alt-del, same as systemd
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    let mut interrupt = signal(SignalKind::interrupt()).unwrap();
+    let shutdown = select! {
+        _ = terminate.recv() => Shutdown::PowerOff,
+        _ = interrupt.recv() => Shutdown::Reboot,
+    };
+    println!("{:?}! stopping units ...", shutdown);
+    stop_units(&actors).await;
+    shutdown
 }
@@ -0,0 +1,221 @@
+use std::{
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::time::timeout;
+
+use super::{
+    Extra, RtMsg, StartError, State, Unit, UnitCommon, UnitDeps, UnitHandle, UnitId, UnitImpl,
+    UnitKind,
+};
+use crate::{
+    util::{
+        device::{is_device_present, resolve_node, UeventSocket, SYSFS},
+        loader::empty_str,
+        mount::path_to_unit_name,
+    },
+    Rc,
+};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Impl {
+    /// the device node, or a link to it like `/dev/disk/by-uuid/...`
+    node: Rc<Path>,
+    /// the root of the sysfs tree to enumerate the devices
+    sysfs: Rc<Path>,
+    /// how long to wait for the device to show up, like `JobTimeoutSec=`
+    timeout: Duration,
+}
+
+/// the default of `x-systemd.device-timeout=`, same as systemd's `DefaultDeviceTimeoutSec=`
+pub(crate) const DEVICE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// the device unit of the node, only the block device nodes in `/dev` have one \
+/// the nodes not present yet, like `/dev/disk/by-uuid/...`, are taken as block devices
+pub(crate) fn device_unit_id(node: &Path) -> Option<UnitId> {
+    if !node.starts_with("/dev")
+        || std::fs::metadata(node).is_ok_and(|m| !m.file_type().is_block_device())
+    {
+        return None;
+    }
+    Some(UnitId::from(
+        path_to_unit_name(node.to_str()?, "device").as_str(),
+    ))
+}
+
+/// watch the uevents, the device is dead once removed
+pub(super) struct Handle {
+    /// the resolved device node, matched against the removed ones
+    node: PathBuf,
+    uevents: UeventSocket,
+}
+
+/// wait until an uevent of `action`, like `add` or `remove`, comes, and return its device node
+async fn wait_uevent(uevents: &UeventSocket, action: &str) -> Result<PathBuf, ()> {
+    loop {
+        match uevents.recv().await {
+            Ok(uevent) if &*uevent.action == action => {
+                if let Some(node) = uevent.node {
+                    return Ok(node);
+                }
+            }
+            Ok(_) => (),
+            Err(e) => {
+                println!("device: failed to receive uevents: {}", e);
+                return Err(());
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl super::Handle for Handle {
+    async fn stop(self: Box<Self>) -> Result<(), UnitHandle> {
+        Ok(())
+    }
+
+    async fn wait(&mut self) -> RtMsg {
+        match wait_uevent(&self.uevents, "remove").await {
+            Ok(node) if node == self.node => RtMsg::Exit(State::Stopped),
+            Ok(_) => RtMsg::Yield,
+            Err(()) => RtMsg::Exit(State::Failed),
+        }
+    }
+}
+
+impl UnitImpl<Impl> {
+    pub(crate) fn new(node: &Path, timeout: Duration) -> Self {
+        Self::with_sysfs(node, Path::new(SYSFS), timeout)
+    }
+
+    pub(crate) fn with_sysfs(node: &Path, sysfs: &Path, timeout: Duration) -> Self {
+        let name = path_to_unit_name(node.to_str().unwrap(), "device");
+        Self {
+            common: UnitCommon {
+                name: name.into(),
+                description: node.to_string_lossy().into(),
+                documentation: empty_str(),
+                deps: Default::default(),
+            },
+            sub: Impl {
+                node: node.into(),
+                sysfs: sysfs.into(),
+                timeout,
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Unit for UnitImpl<Impl> {
+    fn name(&self) -> Rc<str> {
+        Rc::clone(&self.common.name)
+    }
+
+    fn description(&self) -> Rc<str> {
+        Rc::clone(&self.common.description)
+    }
+
+    fn documentation(&self) -> Rc<str> {
+        Rc::clone(&self.common.documentation)
+    }
+
+    fn kind(&self) -> UnitKind {
+        UnitKind::Device
+    }
+
+    fn deps(&self) -> Rc<UnitDeps> {
+        self.common.deps.clone()
+    }
+
+    /// wait until the device shows up, and fail once the timeout expires, \
+    /// so a missing disk does not block the units after it forever
+    async fn start(&self, _: Extra) -> Result<UnitHandle, StartError> {
+        // listen before checking, so that no uevent is missed
+        let uevents = match UeventSocket::new() {
+            Ok(uevents) => uevents,
+            Err(e) => {
+                println!("device: failed to listen to uevents: {}", e);
+                return Err(StartError::default());
+            }
+        };
+        // scan the sysfs once, then match the added nodes, \
+        // the links are created by udev before it sends the uevent
+        if !is_device_present(&self.sub.sysfs, &self.sub.node) {
+            let node = resolve_node(&self.sub.node);
+            let added = async {
+                while wait_uevent(&uevents, "add").await? != node {}
+                Ok::<(), ()>(())
+            };
+            match timeout(self.sub.timeout, added).await {
+                Ok(result) => result?,
+                Err(_) => {
+                    println!("device: timed out waiting for {}", self.sub.node.display());
+                    return Err(StartError::default());
+                }
+            }
+        }
+        Ok(Box::new(Handle {
+            node: resolve_node(&self.sub.node),
+            uevents,
+        }))
+    }
+
+    async fn stop(&self, handle: UnitHandle) -> Result<(), ()> {
+        handle.stop().await.or(Err(()))
+    }
+
+    async fn restart(&self, handle: UnitHandle, extra: Extra) -> Result<UnitHandle, StartError> {
+        self.stop(handle).await?;
+        self.start(extra).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::Path,
+        time::{Duration, Instant},
+    };
+
+    use super::device_unit_id;
+    use crate::{
+        unit::{Extra, Unit, UnitId, UnitImpl},
+        util::device::UeventSocket,
+    };
+
+    #[test]
+    fn test_device_unit_id() {
+        assert_eq!(
+            device_unit_id(Path::new("/dev/disk/by-uuid/abcd-1234")),
+            Some(UnitId::from("dev-disk-by\\x2duuid-abcd\\x2d1234.device"))
+        );
+        assert_eq!(device_unit_id(Path::new("/srv/disk.img")), None);
+        // not block devices
+        assert_eq!(device_unit_id(Path::new("/dev")), None);
+        assert_eq!(device_unit_id(Path::new("/dev/null")), None);
+    }
+
+    #[test]
+    fn test_device_timeout() {
+        let sysfs = std::env::temp_dir().join(format!("sysrs-empty-sysfs-{}", std::process::id()));
+        let timeout = Duration::from_millis(100);
+        let device = UnitImpl::with_sysfs(Path::new("/dev/sysrs-missing"), &sysfs, timeout);
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                // no uevents to wait for without netlink
+                if UeventSocket::new().is_err() {
+                    return;
+                }
+                let now = Instant::now();
+                assert!(device.start(Extra::default()).await.is_err());
+                assert!(now.elapsed() >= timeout);
+            });
+    }
+}
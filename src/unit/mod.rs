@@ -1,17 +1,25 @@
-use std::fmt::{Debug, Display};
+use std::{
+    fmt::{Debug, Display},
+    os::fd::OwnedFd,
+    process::ExitStatus,
+    str::FromStr,
+};
 
 use async_trait::async_trait;
-use tokio::{
-    process::{ChildStderr, ChildStdin, ChildStdout},
-    sync::oneshot,
-};
+use futures::future::pending;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
 
 use crate::Rc;
 
+pub(crate) mod device;
 pub(crate) mod mount;
+pub(crate) mod path;
 pub(crate) mod service;
 pub(crate) mod socket;
+pub(crate) mod swap;
 pub(crate) mod target;
+pub(crate) mod timer;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub enum State {
@@ -45,10 +53,6 @@ impl State {
     pub(crate) fn is_dead(&self) -> bool {
         matches!(self, State::Uninit | State::Stopped | State::Failed)
     }
-
-    pub(crate) fn is_stopped(&self) -> bool {
-        matches!(self, State::Stopped)
-    }
 }
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
@@ -58,6 +62,9 @@ pub(crate) enum UnitKind {
     Mount,
     Target,
     Socket,
+    Path,
+    Swap,
+    Device,
 }
 
 impl Display for UnitKind {
@@ -68,6 +75,9 @@ impl Display for UnitKind {
             UnitKind::Mount => "mount",
             UnitKind::Target => "target",
             UnitKind::Socket => "socket",
+            UnitKind::Path => "path",
+            UnitKind::Swap => "swap",
+            UnitKind::Device => "device",
         };
         f.write_str(s)
     }
@@ -81,6 +91,38 @@ pub(crate) struct UnitCommon {
     deps: Rc<UnitDeps>, // todo
 }
 
+/// how the jobs of a request treat the existing ones, like `--job-mode=` of systemctl
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum JobMode {
+    /// start or stop the units, replacing the conflicting pending jobs
+    #[default]
+    Replace,
+    /// fail the request if it conflicts with the pending jobs
+    Fail,
+    /// start the unit, and stop all the others not pulled in by it
+    Isolate,
+    /// only the unit itself, ignoring all its deps including the ordering ones
+    IgnoreDependencies,
+    /// only the unit itself, still ordered by `after`/`before`
+    IgnoreRequirements,
+}
+
+impl FromStr for JobMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "replace" => JobMode::Replace,
+            "fail" => JobMode::Fail,
+            "isolate" => JobMode::Isolate,
+            "ignore-dependencies" => JobMode::IgnoreDependencies,
+            "ignore-requirements" => JobMode::IgnoreRequirements,
+            _ => return Err(format!("unknown job mode `{}`", s)),
+        })
+    }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct UnitDeps {
     pub requires: Box<[UnitId]>,
@@ -88,9 +130,32 @@ pub(crate) struct UnitDeps {
     pub after: Box<[UnitId]>,
     pub before: Box<[UnitId]>,
     pub conflicts: Box<[UnitId]>,
+    /// like `requires`, and stop this unit once they are dead for any reason
+    pub binds_to: Box<[UnitId]>,
+    /// stop this unit when they are stopped, but not the other way around
+    pub part_of: Box<[UnitId]>,
+    /// like `requires`, but fail at once if they are not active already
+    pub requisite: Box<[UnitId]>,
+    /// like `wants`, and start them again whenever they are dead while this unit is active
+    pub upholds: Box<[UnitId]>,
+    /// reload them as well when this unit is reloaded
+    pub propagates_reload_to: Box<[UnitId]>,
+    /// units to start when this unit fails, or stops cleanly, with `MONITOR_*` in their env
+    pub on_failure: Box<[UnitId]>,
+    pub on_success: Box<[UnitId]>,
+    pub on_failure_job_mode: JobMode,
+    /// units activated by this unit, like the service of a socket
+    pub triggers: Box<[UnitId]>,
+    /// units which activate this unit, like the sockets of a service
+    pub triggered_by: Box<[UnitId]>,
+    /// reverse deps: units which should want/require this unit, like `WantedBy=` of systemd
+    pub wanted_by: Box<[UnitId]>,
+    pub required_by: Box<[UnitId]>,
+    /// mount units covering some paths, which become `requires` + `after` once loaded
+    pub requires_mounts_for: Box<[UnitId]>,
 }
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub(crate) struct UnitId {
     name: Rc<str>,
 }
@@ -120,20 +185,56 @@ impl UnitId {
             "mount" => UnitKind::Mount,
             "target" => UnitKind::Target,
             "socket" => UnitKind::Socket,
+            "path" => UnitKind::Path,
+            "swap" => UnitKind::Swap,
+            "device" => UnitKind::Device,
             _ => unreachable!(),
         }
     }
 }
 
-type ChildStdio = (ChildStdin, ChildStdout, ChildStderr);
+/// extra environment of the started process, like `MONITOR_*` of the `on_failure` units
+pub(crate) type Env = Vec<(&'static str, String)>;
+
+/// runtime info passed to the unit when starting
+#[derive(Debug, Default)]
 pub(crate) struct Extra {
-    pub basic_io: Option<oneshot::Sender<ChildStdio>>,
+    /// listening sockets from the units triggering this unit, see `sd_listen_fds(3)`
+    pub fds: Vec<OwnedFd>,
+    /// states of the units triggered by this unit
+    pub triggers: Vec<(UnitId, watch::Receiver<State>)>,
+    /// passed along with the start job, see `Env`
+    pub env: Env,
+}
+
+/// wait for the next state of a triggered unit, pending forever if not watched
+pub(super) async fn state_changed(state: &mut Option<watch::Receiver<State>>) -> State {
+    if let Some(state) = state {
+        if state.changed().await.is_ok() {
+            return *state.borrow_and_update();
+        }
+    }
+    pending().await
+}
+
+/// why the unit failed to start
+#[derive(Debug, Default)]
+pub(crate) struct StartError {
+    /// how the start command exited, like the one of a oneshot service
+    pub exit_status: Option<ExitStatus>,
+}
+
+/// failed without an exit status
+impl From<()> for StartError {
+    fn from(_: ()) -> Self {
+        Self::default()
+    }
 }
 
 pub(crate) enum RtMsg {
     Yield,
     Exit(State),
-    TriggerStart(UnitId, Extra),
+    TriggerStart(UnitId),
 }
 
 #[async_trait]
@@ -143,6 +244,21 @@ pub(crate) trait Handle: Send {
 
     /// monitor runtime state, and return messages including rt notice or exit state...
     async fn wait(&mut self) -> RtMsg;
+
+    /// dup the listening fds held by the running unit, which will be passed to the triggered units
+    fn listen_fds(&self) -> Vec<OwnedFd> {
+        Vec::new()
+    }
+
+    /// next and last elapse of the running timer
+    fn timer_info(&self) -> Option<timer::TimerInfo> {
+        None
+    }
+
+    /// how the main process exited, once `wait` returns `RtMsg::Exit`
+    fn exit_status(&self) -> Option<ExitStatus> {
+        None
+    }
 }
 type UnitHandle = Box<dyn Handle>;
 
@@ -157,12 +273,17 @@ pub(crate) trait Unit: Debug {
 
     /// start the unit, return a handle which
     /// contains runtime info needed for monitor and stop/kill
-    async fn start(&self) -> Result<UnitHandle, ()>; // todo: error type
+    async fn start(&self, extra: Extra) -> Result<UnitHandle, StartError>;
 
     /// do things needed to stop the unit
     async fn stop(&self, handle: UnitHandle) -> Result<(), ()>;
 
-    async fn restart(&self, handle: UnitHandle) -> Result<UnitHandle, ()>;
+    async fn restart(&self, handle: UnitHandle, extra: Extra) -> Result<UnitHandle, StartError>;
+
+    /// apply the configuration to the running unit without stopping it, nothing to do by default
+    async fn reload(&self, _handle: &mut UnitHandle) -> Result<(), ()> {
+        Ok(())
+    }
 }
 
 pub(crate) type UnitObj = Rc<dyn Unit + Send + Sync + 'static>;
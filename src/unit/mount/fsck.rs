@@ -0,0 +1,142 @@
+use std::{collections::HashSet, path::Path};
+
+use crate::{
+    fstab::FsEntry,
+    unit::{
+        mount::mount_device_unit_id,
+        service::{Impl as ServiceImpl, Kind},
+        UnitCommon, UnitDeps, UnitId, UnitImpl,
+    },
+    util::{
+        loader::{empty_str, extend_unitids},
+        mount::path_to_unit_name,
+    },
+    Rc,
+};
+
+/// the exit code of `fsck(8)` is a bit mask, only 1 for errors corrected is a success, \
+/// the bit 2 asks for a reboot and 4 and above for errors left, these fail the mount
+const FSCK_SUCCESS_EXIT_STATUS: [i32; 1] = [1];
+
+/// only the devices with non-zero passno are checked
+fn needs_fsck(entry: &FsEntry) -> bool {
+    entry.fsck_order != 0 && entry.mount_info.fs_spec.starts_with("/dev")
+}
+
+/// the root is checked first, then the others in the order of passno
+fn fsck_pass(entry: &FsEntry) -> (bool, u8) {
+    (
+        &*entry.mount_info.mount_point != Path::new("/"),
+        entry.fsck_order,
+    )
+}
+
+/// `fsck@<dev>.service` of the device, like `systemd-fsck@.service`
+pub(crate) fn fsck_unit_id(entry: &FsEntry) -> Option<UnitId> {
+    if !needs_fsck(entry) {
+        return None;
+    }
+    let dev = entry.mount_info.fs_spec.to_str()?;
+    Some(UnitId::from(
+        format!("fsck@{}", path_to_unit_name(dev, "service")).as_str(),
+    ))
+}
+
+/// fsck units of the entries, the ones with the same pass run in parallel
+pub(crate) fn fsck_units(entries: &[FsEntry]) -> Vec<UnitImpl<ServiceImpl>> {
+    let mut entries: Vec<&FsEntry> = entries.iter().filter(|e| needs_fsck(e)).collect();
+    entries.sort_by_key(|e| fsck_pass(e));
+    let mut names = HashSet::new();
+    entries
+        .iter()
+        .filter_map(|&entry| {
+            let id = fsck_unit_id(entry)?;
+            // the same device mounted on several mount points is checked once
+            if !names.insert(id.clone()) {
+                return None;
+            }
+            let mut after: Box<[UnitId]> = entries
+                .iter()
+                .take_while(|e| fsck_pass(e) < fsck_pass(entry))
+                .filter_map(|e| fsck_unit_id(e))
+                .collect();
+            // the device should show up first
+            let requires: Box<[UnitId]> = mount_device_unit_id(&entry.mount_info)
+                .into_iter()
+                .collect();
+            extend_unitids(&mut after, &requires);
+            let dev = entry.mount_info.fs_spec.to_string_lossy();
+            Some(UnitImpl {
+                common: UnitCommon {
+                    name: id.to_string().into(),
+                    description: format!("File System Check on {}", dev).into(),
+                    documentation: empty_str(),
+                    deps: UnitDeps {
+                        requires,
+                        after,
+                        ..Default::default()
+                    }
+                    .into(),
+                },
+                // `-M` skips the mounted ones, like the root mounted read-write by the initrd
+                sub: ServiceImpl::new(
+                    Kind::Oneshot,
+                    format!("fsck -a -T -l -M {}", dev).into(),
+                    empty_str(),
+                    empty_str(),
+                    Rc::from(FSCK_SUCCESS_EXIT_STATUS),
+                ),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fsck_unit_id, fsck_units};
+    use crate::{
+        fstab::FsEntry,
+        unit::{Unit, UnitId},
+    };
+
+    #[test]
+    fn test_fsck_units() {
+        let entries: Vec<FsEntry> = [
+            "/dev/sda2 /home ext4 defaults 0 2",
+            "/dev/sda1 / ext4 defaults 0 1",
+            "/dev/sda3 /srv ext4 defaults 0 2",
+            "/dev/sda4 /data ext4 defaults 0 3",
+            "tmpfs /tmp tmpfs defaults 0 0",
+            "/dev/sda5 /mnt ext4 defaults 0 0",
+        ]
+        .into_iter()
+        .map(|line| FsEntry::try_from(line).unwrap())
+        .collect();
+        assert_eq!(
+            fsck_unit_id(&entries[0]),
+            Some(UnitId::from("fsck@dev-sda2.service"))
+        );
+        assert_eq!(fsck_unit_id(&entries[4]), None);
+        assert_eq!(fsck_unit_id(&entries[5]), None);
+
+        let units = fsck_units(&entries);
+        let names: Vec<_> = units.iter().map(|u| u.name()).collect();
+        assert_eq!(
+            names.iter().map(|n| n.as_ref()).collect::<Vec<_>>(),
+            [
+                "fsck@dev-sda1.service",
+                "fsck@dev-sda2.service",
+                "fsck@dev-sda3.service",
+                "fsck@dev-sda4.service"
+            ]
+        );
+        let root = UnitId::from("fsck@dev-sda1.service");
+        assert_eq!(&*units[0].deps().after, &[UnitId::from("dev-sda1.device")]);
+        // the same pass runs in parallel
+        let sda2 = UnitId::from("dev-sda2.device");
+        assert_eq!(&*units[1].deps().after, &[root.clone(), sda2.clone()]);
+        assert_eq!(&*units[1].deps().requires, &[sda2]);
+        assert_eq!(units[2].deps().after[0], root);
+        assert_eq!(units[3].deps().after.len(), 4);
+    }
+}
@@ -1,18 +1,29 @@
+use std::{
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::Path,
+};
+
 use async_trait::async_trait;
 use futures::future::pending;
-use rustix::fs::{MountFlags, UnmountFlags};
+use rustix::fs::{major, minor, MountFlags, UnmountFlags};
 
 use crate::{
-    fstab::{FsEntry, MountInfo},
-    unit::{Unit, UnitKind},
+    fstab::{is_network_spec, FsEntry, MountInfo},
+    unit::{device::device_unit_id, Unit, UnitId, UnitKind},
     util::{
-        loader::{empty_dep, empty_str},
-        mount::{mount, mount_point_to_unit_name, unmount},
+        loader::{empty_str, extend_unitids, str_to_mount_unitids},
+        mount::{
+            mount, mount_point_to_unit_name, path_to_unit_name, remount, unmount, MountOptions,
+            MountTable, ProcMountInfoLine,
+        },
     },
     Rc,
 };
 
-use super::{RtMsg, State, UnitCommon, UnitDeps, UnitHandle, UnitImpl};
+use self::fsck::fsck_unit_id;
+use super::{Extra, RtMsg, StartError, UnitCommon, UnitDeps, UnitHandle, UnitImpl};
+
+pub(crate) mod fsck;
 
 pub(crate) type Impl = Rc<MountInfo>;
 pub(super) struct Handle;
@@ -34,18 +45,219 @@ impl From<FsEntry> for Impl {
     }
 }
 
-impl From<Impl> for UnitImpl<Impl> {
-    fn from(value: Impl) -> Self {
+/// a unit name, or the absolute path of a device node or a mount point as in `x-systemd.requires=/dev/sdb1`
+fn dep_unit_id(value: &str) -> UnitId {
+    if !value.starts_with('/') {
+        return UnitId::from(value);
+    }
+    device_unit_id(Path::new(value))
+        .unwrap_or_else(|| UnitId::from(path_to_unit_name(value, "mount").as_str()))
+}
+
+/// deps of the mount unit, from the options in fstab, see `systemd.mount(5)`
+fn mount_deps(mount_info: &MountInfo) -> UnitDeps {
+    let options: Vec<&str> = mount_info.mount_options.split(',').map(str::trim).collect();
+    let has = |option: &str| options.contains(&option);
+    let values = |key: &str| -> Box<[UnitId]> {
+        options
+            .iter()
+            .filter_map(|o| o.strip_prefix(key)?.strip_prefix('='))
+            .map(dep_unit_id)
+            .collect()
+    };
+
+    let mut deps = UnitDeps {
+        requires: values("x-systemd.requires"),
+        after: values("x-systemd.after"),
+        before: values("x-systemd.before"),
+        wanted_by: values("x-systemd.wanted-by"),
+        required_by: values("x-systemd.required-by"),
+        requires_mounts_for: options
+            .iter()
+            .filter_map(|o| o.strip_prefix("x-systemd.requires-mounts-for="))
+            .flat_map(|paths| str_to_mount_unitids(paths).into_vec())
+            .collect(),
+        ..Default::default()
+    };
+    // required units are also ordered before the mount
+    let requires = deps.requires.clone();
+    extend_unitids(&mut deps.after, &requires);
+
+    // the default target is skipped when the wanting/requiring units are given explicitly
+    if !has("noauto") && deps.wanted_by.is_empty() && deps.required_by.is_empty() {
+        let network = has("_netdev") || is_network_spec(mount_info.fs_spec.to_str().unwrap_or(""));
+        let target = UnitId::from(if network {
+            "remote-fs.target"
+        } else {
+            "local-fs.target"
+        });
+        if has("nofail") {
+            // the target will not wait for or fail due to the mount
+            deps.wanted_by = Box::new([target]);
+        } else {
+            deps.required_by = Box::new([target.clone()]);
+            extend_unitids(&mut deps.before, &[target]);
+        }
+    }
+    deps
+}
+
+/// the nearest mount point containing the path
+fn find_mount<'a>(
+    mut ancestors: impl Iterator<Item = &'a Path>,
+    mount_points: &[&'a Path],
+) -> Option<&'a Path> {
+    ancestors.find(|p| mount_points.contains(p))
+}
+
+/// implicit deps on the mounts of the parent dir and the backing file, see `systemd.mount(5)` \
+/// all mount points come from the same fstab
+fn hierarchy_deps(mount_info: &MountInfo, mount_points: &[&Path]) -> Box<[UnitId]> {
+    let parent = find_mount(mount_info.mount_point.ancestors().skip(1), mount_points);
+    // device nodes are on devtmpfs, which is never in fstab
+    let source = Some(&*mount_info.fs_spec)
+        .filter(|s| s.is_absolute() && !s.starts_with("/dev"))
+        .and_then(|s| find_mount(s.ancestors(), mount_points))
+        .filter(|&m| m != &*mount_info.mount_point);
+    let mut ids: Vec<UnitId> = parent
+        .into_iter()
+        .chain(source)
+        .map(|m| UnitId::from(mount_point_to_unit_name(m.to_str().unwrap()).as_str()))
+        .collect();
+    ids.dedup();
+    ids.into()
+}
+
+fn is_remount(mount_info: &MountInfo) -> bool {
+    MountOptions::from(mount_info.mount_options.as_ref()).remount
+}
+
+/// create the missing mount point, and the upper and work dirs of overlayfs
+async fn make_mount_dirs(mount_info: &MountInfo) {
+    let overlay_dirs = mount_info
+        .mount_options
+        .split(',')
+        .filter(|_| mount_info.vfs_type.as_ref() == "overlay")
+        .filter_map(|o| {
+            o.strip_prefix("upperdir=")
+                .or_else(|| o.strip_prefix("workdir="))
+        })
+        .map(Path::new);
+    for dir in std::iter::once(&*mount_info.mount_point).chain(overlay_dirs) {
+        if let Err(e) = tokio::fs::create_dir_all(dir).await {
+            println!("mount: failed to create {:?}: {}", dir, e);
+        }
+    }
+}
+
+/// whether the mount point is mounted already, e.g. `/` mounted by the initrd
+/// the top mount on the mount point
+async fn mounted_on(mount_point: &Path) -> Option<ProcMountInfoLine> {
+    MountTable::read().await.ok()?.find(mount_point).cloned()
+}
+
+/// `major:minor` of the block device, following the links like `/dev/disk/by-uuid/...`
+fn block_device_number(node: &Path) -> Option<(u32, u32)> {
+    let metadata = std::fs::metadata(node).ok()?;
+    let rdev = metadata.rdev();
+    metadata
+        .file_type()
+        .is_block_device()
+        .then(|| (major(rdev), minor(rdev)))
+}
+
+/// whether the filesystem mounted is the one of the unit, \
+/// block devices are compared by the device number since the root one shows up as `/dev/root`, \
+/// the source of bind mounts is not shown at all
+fn is_same_mount(mount_info: &MountInfo, mounted: &ProcMountInfoLine) -> bool {
+    let options = MountOptions::from(mount_info.mount_options.as_ref());
+    if options.flags.contains(MountFlags::BIND) {
+        return true;
+    }
+    let vfs_type = mount_info.vfs_type.as_ref();
+    if !matches!(vfs_type, "auto" | "none") && vfs_type != mounted.fs_type.as_ref() {
+        return false;
+    }
+    match block_device_number(&mount_info.fs_spec) {
+        Some(dev) => dev == mounted.st_dev,
+        None => *mount_info.fs_spec == *Path::new(mounted.mount_source.as_ref()),
+    }
+}
+
+/// the device unit of the mounted device, bind mounts have none even if the source is in `/dev`
+pub(crate) fn mount_device_unit_id(mount_info: &MountInfo) -> Option<UnitId> {
+    let options = MountOptions::from(mount_info.mount_options.as_ref());
+    if options.flags.contains(MountFlags::BIND) {
+        return None;
+    }
+    device_unit_id(&mount_info.fs_spec)
+}
+
+impl UnitImpl<Impl> {
+    /// mount unit ordered after the mounts of its parent dir and backing file
+    pub(crate) fn with_mount_points(value: Impl, mount_points: &[&Path]) -> Self {
+        let deps = mount_deps(&value);
+        Self::with_deps(value, deps, mount_points)
+    }
+
+    /// mount unit of the fstab entry, checked by its fsck unit first if passno is non-zero
+    pub(crate) fn from_fstab(entry: &FsEntry, mount_points: &[&Path]) -> Self {
+        let mut deps = mount_deps(&entry.mount_info);
+        if let Some(fsck) = fsck_unit_id(entry) {
+            extend_unitids(&mut deps.requires, std::slice::from_ref(&fsck));
+            extend_unitids(&mut deps.after, &[fsck]);
+        }
+        Self::with_deps(entry.mount_info.clone(), deps, mount_points)
+    }
+
+    fn with_deps(value: Impl, mut deps: UnitDeps, mount_points: &[&Path]) -> Self {
         let name = value.mount_point.to_str().unwrap();
         let name = mount_point_to_unit_name(name).into();
+        // stopping happens in reverse, since the parents are required
+        let parents = hierarchy_deps(&value, mount_points);
+        extend_unitids(&mut deps.requires, &parents);
+        extend_unitids(&mut deps.after, &parents);
+        // wait for the device showing up
+        if let Some(device) = mount_device_unit_id(&value) {
+            extend_unitids(&mut deps.requires, std::slice::from_ref(&device));
+            extend_unitids(&mut deps.after, &[device]);
+        }
         let common = UnitCommon {
             name,
-            description: empty_str(),
+            description: value.mount_point.to_string_lossy().into(),
             documentation: empty_str(),
-            deps: empty_dep(),
+            deps: deps.into(),
         };
         Self { common, sub: value }
     }
+
+    /// a mount found in the mount table but not configured, like mounted by hand \
+    /// nothing depends on it, and it depends on nothing
+    pub(crate) fn external(mount: &ProcMountInfoLine) -> Self {
+        let name = mount_point_to_unit_name(&mount.mount_point).into();
+        let mount_info = MountInfo {
+            fs_spec: Path::new(mount.mount_source.as_ref()).into(),
+            mount_point: Path::new(mount.mount_point.as_ref()).into(),
+            vfs_type: mount.fs_type.as_ref().into(),
+            mount_options: mount.mount_options.as_ref().into(),
+        };
+        let common = UnitCommon {
+            name,
+            description: Rc::clone(&mount.mount_point),
+            documentation: empty_str(),
+            deps: Default::default(),
+        };
+        Self {
+            common,
+            sub: mount_info.into(),
+        }
+    }
+}
+
+impl From<Impl> for UnitImpl<Impl> {
+    fn from(value: Impl) -> Self {
+        Self::with_mount_points(value, &[])
+    }
 }
 
 impl From<FsEntry> for UnitImpl<Impl> {
@@ -73,15 +285,37 @@ impl Unit for UnitImpl<Impl> {
         UnitKind::Mount
     }
 
-    async fn start(&self) -> Result<UnitHandle, ()> {
+    async fn start(&self, _: Extra) -> Result<UnitHandle, StartError> {
         let Self {
             common: _,
             sub: mount_info,
         } = self;
+        // `remount` applies the options to the mounted filesystem
+        if let Some(mounted) = mounted_on(&mount_info.mount_point)
+            .await
+            .filter(|_| !is_remount(mount_info))
+        {
+            if is_same_mount(mount_info, &mounted) {
+                return Ok(Box::new(Handle));
+            }
+            println!(
+                "mount: {} is taken by {} ({}), not {} ({})",
+                mount_info.mount_point.display(),
+                mounted.mount_source,
+                mounted.fs_type,
+                mount_info.fs_spec.display(),
+                mount_info.vfs_type
+            );
+            return Err(StartError::default());
+        }
+        make_mount_dirs(mount_info).await;
         let mount_info = mount_info.clone();
         match tokio::task::block_in_place(|| mount(mount_info, MountFlags::empty())) {
             Ok(_) => Ok(Box::new(Handle)),
-            Err(_) => Err(()),
+            Err(e) => {
+                println!("mount: failed to mount {}: {}", self.common.name, e);
+                Err(StartError::default())
+            }
         }
     }
 
@@ -90,6 +324,10 @@ impl Unit for UnitImpl<Impl> {
             common: _,
             sub: mount_info,
         } = self;
+        // the filesystem is not mounted by the unit
+        if is_remount(mount_info) {
+            return Ok(());
+        }
         let mount_info = mount_info.clone();
         match tokio::task::block_in_place(|| unmount(mount_info, UnmountFlags::empty())) {
             Ok(_) => Ok(()),
@@ -97,12 +335,129 @@ impl Unit for UnitImpl<Impl> {
         }
     }
 
-    async fn restart(&self, handle: UnitHandle) -> Result<UnitHandle, ()> {
+    async fn restart(&self, handle: UnitHandle, extra: Extra) -> Result<UnitHandle, StartError> {
         self.stop(handle).await?;
-        self.start().await
+        self.start(extra).await
+    }
+
+    /// remount with the options, rather than unmounting and mounting again
+    async fn reload(&self, _: &mut UnitHandle) -> Result<(), ()> {
+        let mount_info = self.sub.clone();
+        match tokio::task::block_in_place(|| remount(mount_info, MountFlags::empty())) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                println!("mount: failed to remount {}: {}", self.common.name, e);
+                Err(())
+            }
+        }
     }
 
     fn deps(&self) -> Rc<UnitDeps> {
-        todo!()
+        self.common.deps.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{hierarchy_deps, is_same_mount, mount_deps, mount_device_unit_id};
+    use crate::{
+        fstab::FsEntry,
+        unit::{UnitDeps, UnitId},
+        util::mount::ProcMountInfoLine,
+    };
+
+    fn deps(line: &str) -> UnitDeps {
+        mount_deps(&FsEntry::try_from(line).unwrap().mount_info)
+    }
+
+    fn ids(names: &[&str]) -> Box<[UnitId]> {
+        names.iter().map(|&n| UnitId::from(n)).collect()
+    }
+
+    #[test]
+    fn test_mount_deps() {
+        let d = deps("/dev/sda1 /data ext4 defaults 0 2");
+        assert_eq!(d.required_by, ids(&["local-fs.target"]));
+        assert_eq!(d.before, ids(&["local-fs.target"]));
+
+        let d = deps("/dev/sda1 /data ext4 nofail 0 2");
+        assert_eq!(d.wanted_by, ids(&["local-fs.target"]));
+        assert!(d.required_by.is_empty() && d.before.is_empty());
+
+        let d = deps("/dev/sda1 /data ext4 noauto 0 2");
+        assert!(d.wanted_by.is_empty() && d.required_by.is_empty());
+
+        let d = deps("server:/export /srv nfs defaults 0 0");
+        assert_eq!(d.required_by, ids(&["remote-fs.target"]));
+
+        let d = deps(
+            "/dev/sdb1 /backup ext4 x-systemd.requires=crypt.service,x-systemd.before=backup.service,x-systemd.wanted-by=backup.service 0 2",
+        );
+        assert_eq!(d.requires, ids(&["crypt.service"]));
+        assert_eq!(d.after, ids(&["crypt.service"]));
+        assert_eq!(d.before, ids(&["backup.service"]));
+        assert_eq!(d.wanted_by, ids(&["backup.service"]));
+        assert!(d.required_by.is_empty());
+
+        // the paths are device nodes or mount points
+        let d =
+            deps("/dev/sdc1 /data ext4 x-systemd.requires=/dev/sdb1,x-systemd.after=/srv/data 0 2");
+        assert_eq!(d.requires, ids(&["dev-sdb1.device"]));
+        assert_eq!(d.after, ids(&["srv-data.mount", "dev-sdb1.device"]));
+    }
+
+    #[test]
+    fn test_mount_device_unit_id() {
+        let device =
+            |line: &str| mount_device_unit_id(&FsEntry::try_from(line).unwrap().mount_info);
+        assert_eq!(
+            device("/dev/sda1 /data ext4 defaults"),
+            Some(UnitId::from("dev-sda1.device"))
+        );
+        assert_eq!(device("/dev /chroot/dev none bind"), None);
+        assert_eq!(device("/dev/sda1 /mnt none rbind,ro"), None);
+        assert_eq!(device("tmpfs /tmp tmpfs defaults"), None);
+    }
+
+    #[test]
+    fn test_is_same_mount() {
+        let same = |line: &str, mounted: &str| {
+            is_same_mount(
+                &FsEntry::try_from(line).unwrap().mount_info,
+                &ProcMountInfoLine::parse(mounted).unwrap(),
+            )
+        };
+        let tmpfs = "40 1 0:35 / /home rw,relatime shared:20 - tmpfs tmpfs rw";
+        assert!(same("tmpfs /home tmpfs defaults", tmpfs));
+        assert!(!same("/dev/sda2 /home ext4 defaults", tmpfs));
+        assert!(!same("/dev/sda2 /home auto defaults", tmpfs));
+        assert!(!same("none /home proc defaults", tmpfs));
+        assert!(same("/srv/home /home none bind", tmpfs));
+    }
+
+    #[test]
+    fn test_hierarchy_deps() {
+        let mount_points = [
+            Path::new("/"),
+            Path::new("/home"),
+            Path::new("/srv"),
+            Path::new("/home/data"),
+        ];
+        let deps = |line: &str| {
+            hierarchy_deps(&FsEntry::try_from(line).unwrap().mount_info, &mount_points)
+        };
+        assert_eq!(deps("/dev/sda1 / ext4 defaults"), ids(&[]));
+        assert_eq!(deps("/dev/sda2 /home ext4 defaults"), ids(&["-.mount"]));
+        assert_eq!(
+            deps("/dev/sda3 /home/data ext4 defaults"),
+            ids(&["home.mount"])
+        );
+        // loop device backed by a file on another mount
+        assert_eq!(
+            deps("/srv/images/data.img /home/data ext4 loop"),
+            ids(&["home.mount", "srv.mount"])
+        );
     }
 }
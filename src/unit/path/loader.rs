@@ -0,0 +1,138 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    unit::{target::add_default_deps, UnitCommon, UnitDeps, UnitId, UnitImpl, UnitKind},
+    util::loader::{
+        default_true, deserialize_mode, empty_str, extend_unitids, str_to_mount_unitids, ExtraDeps,
+    },
+    Rc,
+};
+
+use super::{Impl, PathKind, PathSpec};
+
+/// default permission of the directories created by `make_directory`, same as systemd
+const DEFAULT_DIRECTORY_MODE: u32 = 0o755;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct PathUnit {
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) requires: String,
+    #[serde(default)]
+    pub(crate) wants: String,
+    #[serde(default)]
+    pub(crate) before: String,
+    #[serde(default)]
+    pub(crate) after: String,
+    #[serde(default)]
+    pub(crate) conflicts: String,
+    #[serde(flatten)]
+    pub(crate) extra_deps: ExtraDeps,
+    /// space separated paths, whose mount units are required
+    #[serde(default)]
+    pub(crate) requires_mounts_for: String,
+    /// the unit to start when triggered, default to the service with the same name
+    #[serde(default)]
+    pub(crate) unit: Option<String>,
+    /// the paths below are comma separated lists
+    #[serde(default)]
+    pub(crate) path_exists: String,
+    #[serde(default)]
+    pub(crate) path_exists_glob: String,
+    #[serde(default)]
+    pub(crate) path_changed: String,
+    #[serde(default)]
+    pub(crate) path_modified: String,
+    #[serde(default)]
+    pub(crate) directory_not_empty: String,
+    /// create the watched directories before watching
+    #[serde(default)]
+    pub(crate) make_directory: bool,
+    #[serde(default, deserialize_with = "deserialize_mode")]
+    pub(crate) directory_mode: Option<u32>,
+    /// implicit deps on the standard targets, see `add_default_deps`
+    #[serde(default = "default_true")]
+    pub(crate) default_dependencies: bool,
+}
+
+fn str_to_specs(s: &str, kind: PathKind) -> impl Iterator<Item = PathSpec> + '_ {
+    s.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(move |s| PathSpec {
+            kind,
+            path: Rc::from(Path::new(s)),
+        })
+}
+
+impl From<PathUnit> for UnitImpl<Impl> {
+    fn from(value: PathUnit) -> Self {
+        let PathUnit {
+            name,
+            requires,
+            wants,
+            before,
+            after,
+            conflicts,
+            extra_deps,
+            requires_mounts_for,
+            unit,
+            path_exists,
+            path_exists_glob,
+            path_changed,
+            path_modified,
+            directory_not_empty,
+            make_directory,
+            directory_mode,
+            default_dependencies,
+        } = value;
+
+        let specs = str_to_specs(&path_exists, PathKind::Exists)
+            .chain(str_to_specs(&path_exists_glob, PathKind::ExistsGlob))
+            .chain(str_to_specs(&path_changed, PathKind::Changed))
+            .chain(str_to_specs(&path_modified, PathKind::Modified))
+            .chain(str_to_specs(
+                &directory_not_empty,
+                PathKind::DirectoryNotEmpty,
+            ))
+            .collect();
+
+        let unit = match unit {
+            Some(unit) => UnitId::from(unit.as_str()),
+            None => {
+                let (stem, _) = name.rsplit_once('.').unwrap();
+                UnitId::from(format!("{}.service", stem).as_str())
+            }
+        };
+        let mut deps = UnitDeps::from_strs(&requires, &wants, &before, &after, &conflicts)
+            .with_extra(&extra_deps);
+        deps.requires_mounts_for = str_to_mount_unitids(&requires_mounts_for);
+        // implicit deps: the path unit triggers the unit
+        extend_unitids(&mut deps.before, std::slice::from_ref(&unit));
+        deps.triggers = Box::new([unit.clone()]);
+        if default_dependencies {
+            add_default_deps(&mut deps, UnitKind::Path);
+        }
+
+        Self {
+            common: UnitCommon {
+                name: name.into(),
+                description: empty_str(),
+                documentation: empty_str(),
+                deps: deps.into(),
+            },
+            sub: Impl {
+                unit,
+                specs,
+                make_directory,
+                directory_mode: directory_mode.unwrap_or(DEFAULT_DIRECTORY_MODE),
+            },
+        }
+    }
+}
+
+pub(crate) fn load_path(s: &str) -> UnitImpl<Impl> {
+    toml::from_str::<PathUnit>(s).unwrap().into()
+}
@@ -0,0 +1,296 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use notify::{
+    event::{AccessKind, AccessMode, ModifyKind},
+    Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use tokio::{
+    fs::DirBuilder,
+    select,
+    sync::{mpsc, watch},
+};
+
+use super::{
+    state_changed, Extra, RtMsg, StartError, State, Unit, UnitDeps, UnitHandle, UnitId, UnitImpl,
+    UnitKind,
+};
+use crate::Rc;
+
+pub(crate) mod loader;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PathKind {
+    Exists,
+    /// only the last component of the path may contain `*` and `?`
+    ExistsGlob,
+    Changed,
+    Modified,
+    DirectoryNotEmpty,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct PathSpec {
+    kind: PathKind,
+    path: Rc<Path>,
+}
+
+impl PathSpec {
+    /// the directory to watch, the parent of the path except for `DirectoryNotEmpty`
+    fn dir(&self) -> &Path {
+        match self.kind {
+            PathKind::DirectoryNotEmpty => &self.path,
+            _ => self.path.parent().unwrap_or(&self.path),
+        }
+    }
+
+    /// whether the condition holds right now, only for the state conditions
+    fn check(&self) -> bool {
+        match self.kind {
+            PathKind::Exists => self.path.exists(),
+            PathKind::ExistsGlob => {
+                let Some(pattern) = self.path.file_name().and_then(|s| s.to_str()) else {
+                    return false;
+                };
+                std::fs::read_dir(self.dir()).is_ok_and(|mut dir| {
+                    dir.any(|e| {
+                        e.is_ok_and(|e| {
+                            glob_match(pattern.as_bytes(), e.file_name().as_encoded_bytes())
+                        })
+                    })
+                })
+            }
+            PathKind::DirectoryNotEmpty => {
+                std::fs::read_dir(&self.path).is_ok_and(|mut dir| dir.next().is_some())
+            }
+            PathKind::Changed | PathKind::Modified => false,
+        }
+    }
+
+    /// whether the event triggers the condition, only for the event conditions
+    fn triggered_by(&self, event: &Event) -> bool {
+        let kind_matches = match event.kind {
+            EventKind::Access(AccessKind::Close(AccessMode::Write))
+            | EventKind::Create(_)
+            | EventKind::Remove(_)
+            | EventKind::Modify(ModifyKind::Name(_) | ModifyKind::Metadata(_)) => true,
+            EventKind::Modify(ModifyKind::Data(_)) => self.kind == PathKind::Modified,
+            _ => false,
+        };
+        matches!(self.kind, PathKind::Changed | PathKind::Modified)
+            && kind_matches
+            // changes of the path itself, or the entries in it when it's a directory
+            && event
+                .paths
+                .iter()
+                .any(|p| p.as_path() == &*self.path || p.parent() == Some(&*self.path))
+    }
+}
+
+/// match a file name against a pattern with `*` and `?`
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => {
+            glob_match(rest, name) || (!name.is_empty() && glob_match(pattern, &name[1..]))
+        }
+        (Some((b'?', p)), Some((_, n))) => glob_match(p, n),
+        (Some((a, p)), Some((b, n))) => a == b && glob_match(p, n),
+        _ => false,
+    }
+}
+
+/// the path itself if exists, or the nearest existing ancestor,
+/// which will be watched until the path is created
+fn existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|p| p.is_dir())
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Impl {
+    unit: UnitId,
+    specs: Box<[PathSpec]>,
+    make_directory: bool,
+    directory_mode: u32,
+}
+
+pub(super) struct Handle {
+    path: Impl,
+    watcher: RecommendedWatcher,
+    events: mpsc::UnboundedReceiver<notify::Result<Event>>,
+    watched: HashSet<PathBuf>,
+    /// state of the triggered unit
+    unit_state: Option<watch::Receiver<State>>,
+    /// check the state conditions on the next `wait`
+    recheck: bool,
+}
+
+impl Handle {
+    fn new(path: Impl, unit_state: Option<watch::Receiver<State>>) -> notify::Result<Self> {
+        let (tx, events) = mpsc::unbounded_channel();
+        let watcher = notify::recommended_watcher(move |res| {
+            tx.send(res).ok();
+        })?;
+        let mut handle = Self {
+            path,
+            watcher,
+            events,
+            watched: HashSet::new(),
+            unit_state,
+            recheck: true,
+        };
+        handle.update_watches();
+        Ok(handle)
+    }
+
+    /// watch the dirs of the specs, or their ancestors when they don't exist yet
+    fn update_watches(&mut self) {
+        let mut dirs = HashSet::new();
+        for spec in self.path.specs.iter() {
+            dirs.extend(existing_ancestor(spec.dir()).map(Path::to_path_buf));
+            // changes inside the directory
+            if matches!(spec.kind, PathKind::Changed | PathKind::Modified) && spec.path.is_dir() {
+                dirs.insert(spec.path.to_path_buf());
+            }
+        }
+        for dir in self.watched.difference(&dirs) {
+            self.watcher.unwatch(dir).ok();
+        }
+        for dir in dirs.difference(&self.watched) {
+            if let Err(e) = self.watcher.watch(dir, RecursiveMode::NonRecursive) {
+                println!("path: failed to watch {:?}: {}", dir, e);
+            }
+        }
+        self.watched = dirs;
+    }
+
+    /// the triggered unit is not running, so it can be triggered again
+    fn unit_dead(&self) -> bool {
+        self.unit_state
+            .as_ref()
+            .is_none_or(|state| state.borrow().is_dead())
+    }
+}
+
+#[async_trait]
+impl super::Handle for Handle {
+    async fn stop(self: Box<Self>) -> Result<(), UnitHandle> {
+        Ok(())
+    }
+
+    async fn wait(&mut self) -> RtMsg {
+        if std::mem::take(&mut self.recheck)
+            && self.unit_dead()
+            && self.path.specs.iter().any(PathSpec::check)
+        {
+            return RtMsg::TriggerStart(self.path.unit.clone());
+        }
+        select! {
+            Some(res) = self.events.recv() => {
+                match res {
+                    Ok(event) => {
+                        self.update_watches();
+                        self.recheck = true;
+                        if self.unit_dead() && self.path.specs.iter().any(|s| s.triggered_by(&event)) {
+                            self.recheck = false;
+                            return RtMsg::TriggerStart(self.path.unit.clone());
+                        }
+                    }
+                    Err(e) => println!("path: watch error: {}", e),
+                }
+                RtMsg::Yield
+            }
+            state = state_changed(&mut self.unit_state) => {
+                // the conditions still holding will trigger the unit again
+                self.recheck = state.is_dead();
+                RtMsg::Yield
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Unit for UnitImpl<Impl> {
+    fn name(&self) -> Rc<str> {
+        Rc::clone(&self.common.name)
+    }
+
+    fn description(&self) -> Rc<str> {
+        Rc::clone(&self.common.description)
+    }
+
+    fn documentation(&self) -> Rc<str> {
+        Rc::clone(&self.common.documentation)
+    }
+
+    fn kind(&self) -> UnitKind {
+        UnitKind::Path
+    }
+
+    fn deps(&self) -> Rc<UnitDeps> {
+        self.common.deps.clone()
+    }
+
+    async fn start(&self, extra: Extra) -> Result<UnitHandle, StartError> {
+        if self.sub.make_directory {
+            // same as systemd, the paths checked for existence are never created
+            for spec in self.sub.specs.iter().filter(|s| {
+                matches!(
+                    s.kind,
+                    PathKind::Changed | PathKind::Modified | PathKind::DirectoryNotEmpty
+                )
+            }) {
+                if let Err(e) = DirBuilder::new()
+                    .recursive(true)
+                    .mode(self.sub.directory_mode)
+                    .create(&spec.path)
+                    .await
+                {
+                    println!("path: failed to create {:?}: {}", spec.path, e);
+                }
+            }
+        }
+        let unit_state = extra
+            .triggers
+            .into_iter()
+            .find(|(id, _)| id == &self.sub.unit)
+            .map(|(_, state)| state);
+        match Handle::new(self.sub.clone(), unit_state) {
+            Ok(handle) => Ok(Box::new(handle)),
+            Err(e) => {
+                println!(
+                    "path: failed to create watcher for {}: {}",
+                    self.common.name, e
+                );
+                Err(StartError::default())
+            }
+        }
+    }
+
+    async fn stop(&self, handle: UnitHandle) -> Result<(), ()> {
+        handle.stop().await.or(Err(()))
+    }
+
+    async fn restart(&self, handle: UnitHandle, extra: Extra) -> Result<UnitHandle, StartError> {
+        self.stop(handle).await?;
+        self.start(extra).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::glob_match;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*.job", b"a.job"));
+        assert!(glob_match(b"*.job", b".job"));
+        assert!(glob_match(b"in-??.txt", b"in-01.txt"));
+        assert!(glob_match(b"*", b"anything"));
+        assert!(!glob_match(b"*.job", b"a.job.tmp"));
+        assert!(!glob_match(b"in-??.txt", b"in-1.txt"));
+    }
+}
@@ -1,6 +1,8 @@
 use crate::{
-    unit::UnitCommon,
-    util::loader::{empty_str, str_to_unitids},
+    unit::{target::add_default_deps, UnitCommon, UnitKind},
+    util::loader::{
+        default_true, empty_str, extend_unitids, str_to_mount_unitids, str_to_unitids, ExtraDeps,
+    },
     Rc,
 };
 
@@ -24,12 +26,26 @@ pub(crate) struct Service {
     pub(crate) after: String,
     #[serde(default)]
     pub(crate) conflicts: String,
+    #[serde(flatten)]
+    pub(crate) extra_deps: ExtraDeps,
+    /// space separated paths, whose mount units are required
+    #[serde(default)]
+    pub(crate) requires_mounts_for: String,
+    /// sockets whose fds are passed to the service
+    #[serde(default)]
+    pub(crate) sockets: String,
     pub(crate) kind: Kind,
     pub(crate) start: String,
     #[serde(default)]
     pub(crate) stop: String,
     #[serde(default)]
     pub(crate) restart: String,
+    /// space separated exit codes regarded as success besides 0
+    #[serde(default)]
+    pub(crate) success_exit_status: String,
+    /// implicit deps on the standard targets, see `add_default_deps`
+    #[serde(default = "default_true")]
+    pub(crate) default_dependencies: bool,
 }
 
 impl From<Service> for UnitImpl<Impl> {
@@ -41,26 +57,45 @@ impl From<Service> for UnitImpl<Impl> {
             before,
             after,
             conflicts,
+            extra_deps,
+            requires_mounts_for,
+            sockets,
             kind,
             start,
             stop,
             restart,
+            success_exit_status,
+            default_dependencies,
         } = value;
 
+        let mut deps = UnitDeps::from_strs(&requires, &wants, &before, &after, &conflicts)
+            .with_extra(&extra_deps);
+        deps.requires_mounts_for = str_to_mount_unitids(&requires_mounts_for);
+        // implicit deps: sockets should be listening before the service starts
+        let sockets = str_to_unitids(&sockets);
+        extend_unitids(&mut deps.wants, &sockets);
+        extend_unitids(&mut deps.after, &sockets);
+        deps.triggered_by = sockets;
+        if default_dependencies {
+            add_default_deps(&mut deps, UnitKind::Service);
+        }
+
         Self {
             common: UnitCommon {
                 name: name.into(),
                 description: empty_str(),
                 documentation: empty_str(),
-                deps: Rc::new(UnitDeps::from_strs(
-                    &requires, &wants, &before, &after, &conflicts,
-                )),
+                deps: Rc::new(deps),
             },
             sub: Impl {
                 kind,
                 exec_start: start.into(),
                 exec_stop: stop.into(),
                 exec_restart: restart.into(),
+                success_exit_status: success_exit_status
+                    .split_whitespace()
+                    .filter_map(|code| code.parse().ok())
+                    .collect(),
             },
         }
     }
@@ -85,6 +120,7 @@ impl UnitDeps {
             after,
             before,
             conflicts,
+            ..Default::default()
         }
     }
 }
@@ -1,12 +1,20 @@
-use std::process::Stdio;
+use std::{
+    mem,
+    os::fd::{FromRawFd, OwnedFd, RawFd},
+    process::{ExitStatus, Stdio},
+};
 
 use async_trait::async_trait;
 use futures::future::pending;
+use rustix::io::{dup2, fcntl_dupfd_cloexec};
 use tokio::{io, process::Child};
 
-use super::{RtMsg, State, Unit, UnitDeps, UnitHandle, UnitImpl, UnitKind};
+use super::{Extra, RtMsg, StartError, State, Unit, UnitDeps, UnitHandle, UnitImpl, UnitKind};
 use crate::Rc;
 
+/// the first fd passed to the service, see `sd_listen_fds(3)`
+const SD_LISTEN_FDS_START: RawFd = 3;
+
 pub(crate) mod loader;
 
 #[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
@@ -18,31 +26,50 @@ pub(crate) enum Kind {
 }
 
 pub(crate) enum Handle {
-    Process(tokio::process::Child),
-    Empty,
+    /// the child and the extra exit codes regarded as success
+    Process(tokio::process::Child, Rc<[i32]>),
+    /// the child exited
+    Exited(ExitStatus),
+}
+
+/// exit code 0, or the ones in `success_exit_status`
+fn is_success(status: ExitStatus, success_exit_status: &[i32]) -> bool {
+    status.success()
+        || status
+            .code()
+            .is_some_and(|code| success_exit_status.contains(&code))
 }
 
 #[async_trait]
 impl super::Handle for Handle {
     async fn stop(mut self: Box<Self>) -> Result<(), UnitHandle> {
         match self.as_mut() {
-            Handle::Process(child) => child.kill().await.or(Err(self)),
-            Handle::Empty => Ok(()),
+            Handle::Process(child, _) => child.kill().await.or(Err(self)),
+            Handle::Exited(_) => Ok(()),
         }
     }
     async fn wait(&mut self) -> RtMsg {
         match self {
-            Handle::Process(child) => match child.wait().await {
+            Handle::Process(child, success_exit_status) => match child.wait().await {
                 Ok(exitcode) => {
-                    if exitcode.success() {
-                        RtMsg::Exit(State::Stopped)
+                    let state = if is_success(exitcode, success_exit_status) {
+                        State::Stopped
                     } else {
-                        RtMsg::Exit(State::Failed)
-                    }
+                        State::Failed
+                    };
+                    *self = Handle::Exited(exitcode);
+                    RtMsg::Exit(state)
                 }
                 Err(_) => todo!(),
             },
-            Handle::Empty => pending().await,
+            Handle::Exited(_) => pending().await,
+        }
+    }
+
+    fn exit_status(&self) -> Option<ExitStatus> {
+        match self {
+            Handle::Exited(status) => Some(*status),
+            _ => None,
         }
     }
 }
@@ -53,15 +80,23 @@ pub(crate) struct Impl {
     exec_start: Rc<str>,
     exec_stop: Rc<str>,
     exec_restart: Rc<str>,
+    success_exit_status: Rc<[i32]>,
 }
 
 impl Impl {
-    pub fn new(kind: Kind, start: Rc<str>, stop: Rc<str>, restart: Rc<str>) -> Self {
+    pub fn new(
+        kind: Kind,
+        start: Rc<str>,
+        stop: Rc<str>,
+        restart: Rc<str>,
+        success_exit_status: Rc<[i32]>,
+    ) -> Self {
         Self {
             kind,
             exec_start: start,
             exec_stop: stop,
             exec_restart: restart,
+            success_exit_status,
         }
     }
 }
@@ -88,16 +123,19 @@ impl Unit for UnitImpl<Impl> {
         self.common.deps.clone()
     }
 
-    async fn start(&self) -> Result<UnitHandle, ()> {
+    async fn start(&self, extra: Extra) -> Result<UnitHandle, StartError> {
         let kind = self.sub.kind;
         match kind {
             Kind::Simple => {
                 let exec_start = self.sub.exec_start.clone();
-                match run_cmd(&exec_start) {
-                    Ok(child) => Ok(Box::new(Handle::Process(child))),
+                match run_cmd(&exec_start, &extra.fds, &extra.env) {
+                    Ok(child) => Ok(Box::new(Handle::Process(
+                        child,
+                        self.sub.success_exit_status.clone(),
+                    ))),
                     Err(e) => {
                         println!("{}: {}", self.name(), e);
-                        Err(())
+                        Err(StartError::default())
                     }
                 }
             }
@@ -106,15 +144,29 @@ impl Unit for UnitImpl<Impl> {
                 if self.sub.exec_start.is_empty() {
                     todo!()
                 } else {
-                    match run_cmd(&self.sub.exec_start).unwrap().wait().await {
+                    let mut child = match run_cmd(&self.sub.exec_start, &extra.fds, &extra.env) {
+                        Ok(child) => child,
+                        Err(e) => {
+                            println!("{}: {}", self.name(), e);
+                            return Err(StartError::default());
+                        }
+                    };
+                    match child.wait().await {
+                        // kept for the `MONITOR_*` env of the `on_success`/`on_failure` units
                         Ok(exitcode) => {
-                            if exitcode.success() {
-                                Ok(Box::new(Handle::Empty))
+                            if is_success(exitcode, &self.sub.success_exit_status) {
+                                Ok(Box::new(Handle::Exited(exitcode)))
                             } else {
-                                Err(())
+                                println!("{}: exited with {}", self.name(), exitcode);
+                                Err(StartError {
+                                    exit_status: Some(exitcode),
+                                })
                             }
                         }
-                        Err(_) => todo!(),
+                        Err(e) => {
+                            println!("{}: {}", self.name(), e);
+                            Err(StartError::default())
+                        }
                     }
                 }
             }
@@ -130,15 +182,20 @@ impl Unit for UnitImpl<Impl> {
                 if self.sub.exec_stop.is_empty() {
                     Ok(())
                 } else {
-                    match run_cmd(&self.sub.exec_stop).unwrap().wait().await {
-                        Ok(exitcode) => {
-                            if exitcode.success() {
-                                Ok(())
-                            } else {
-                                Err(())
-                            }
+                    let mut child = match run_cmd(&self.sub.exec_stop, &[], &[]) {
+                        Ok(child) => child,
+                        Err(e) => {
+                            println!("{}: {}", self.name(), e);
+                            return Err(());
+                        }
+                    };
+                    match child.wait().await {
+                        Ok(exitcode) if exitcode.success() => Ok(()),
+                        Ok(_) => Err(()),
+                        Err(e) => {
+                            println!("{}: {}", self.name(), e);
+                            Err(())
                         }
-                        Err(_) => todo!(),
                     }
                 }
             }
@@ -146,13 +203,14 @@ impl Unit for UnitImpl<Impl> {
         }
     }
 
-    async fn restart(&self, handle: UnitHandle) -> Result<UnitHandle, ()> {
+    async fn restart(&self, handle: UnitHandle, extra: Extra) -> Result<UnitHandle, StartError> {
         self.stop(handle).await?;
-        self.start().await
+        self.start(extra).await
     }
 }
 
-fn run_cmd(cmd: &str) -> Result<Child, io::Error> {
+/// run the command, passing `fds` as `SD_LISTEN_FDS_START..` like systemd
+fn run_cmd(cmd: &str, fds: &[OwnedFd], env: &[(&str, String)]) -> Result<Child, io::Error> {
     let cmd = cmd.trim();
     if cmd.is_empty() {
         return Err(io::Error::new(
@@ -161,10 +219,43 @@ fn run_cmd(cmd: &str) -> Result<Child, io::Error> {
         ));
     }
     let mut s = cmd.split_whitespace();
-    tokio::process::Command::new(s.next().unwrap())
+    let mut command = if fds.is_empty() {
+        tokio::process::Command::new(s.next().unwrap())
+    } else {
+        // `LISTEN_PID` should be the pid of the service itself,
+        // so let the shell fill it and then exec into the service
+        let mut command = tokio::process::Command::new("/bin/sh");
+        command
+            .args(["-c", "export LISTEN_PID=$$; exec \"$0\" \"$@\""])
+            .env("LISTEN_FDS", fds.len().to_string());
+        command
+    };
+    command
         .args(s)
+        .envs(env.iter().map(|(k, v)| (k, v)))
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
+        .stderr(Stdio::piped());
+    if !fds.is_empty() {
+        // move the fds out of the target range first,
+        // so that they won't be overwritten in the child
+        let min = SD_LISTEN_FDS_START + fds.len() as RawFd;
+        let fds = fds
+            .iter()
+            .map(|fd| fcntl_dupfd_cloexec(fd, min))
+            .collect::<Result<Vec<_>, _>>()?;
+        // SAFETY: only the async-signal-safe `dup2` is called in the child
+        unsafe {
+            command.pre_exec(move || {
+                for (i, fd) in fds.iter().enumerate() {
+                    let mut target = OwnedFd::from_raw_fd(SD_LISTEN_FDS_START + i as RawFd);
+                    let ret = dup2(fd, &mut target);
+                    mem::forget(target);
+                    ret?;
+                }
+                Ok(())
+            });
+        }
+    }
+    command.spawn()
 }
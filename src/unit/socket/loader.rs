@@ -3,31 +3,68 @@ use std::path::PathBuf;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    unit::{UnitCommon, UnitImpl},
-    util::loader::{empty_dep, empty_str},
+    unit::{target::add_default_deps, UnitCommon, UnitDeps, UnitId, UnitImpl, UnitKind},
+    util::loader::{default_true, deserialize_mode, empty_str, str_to_mount_unitids, ExtraDeps},
 };
 
 use super::Impl;
 
+/// default permission of the socket file, same as systemd
+const DEFAULT_SOCKET_MODE: u32 = 0o666;
+/// default permission of the parent dirs created for the socket
+const DEFAULT_DIRECTORY_MODE: u32 = 0o755;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct Socket {
     name: String,
     path: PathBuf,
     service: String,
+    #[serde(default)]
+    socket_user: Option<String>,
+    #[serde(default)]
+    socket_group: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_mode")]
+    socket_mode: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_mode")]
+    directory_mode: Option<u32>,
+    #[serde(flatten)]
+    extra_deps: ExtraDeps,
+    /// space separated paths, whose mount units are required
+    #[serde(default)]
+    requires_mounts_for: String,
+    /// implicit deps on the standard targets, see `add_default_deps`
+    #[serde(default = "default_true")]
+    default_dependencies: bool,
 }
 
 impl From<Socket> for UnitImpl<Impl> {
     fn from(value: Socket) -> Self {
+        let service = UnitId::from(value.service.as_str());
+        // implicit deps: the socket should listen before the service starts, and triggers it
+        let mut deps = UnitDeps {
+            before: Box::new([service.clone()]),
+            triggers: Box::new([service.clone()]),
+            requires_mounts_for: str_to_mount_unitids(&value.requires_mounts_for),
+            ..Default::default()
+        }
+        .with_extra(&value.extra_deps);
+        if value.default_dependencies {
+            add_default_deps(&mut deps, UnitKind::Socket);
+        }
         Self {
             common: UnitCommon {
                 name: value.name.into(),
                 description: empty_str(),
                 documentation: empty_str(),
-                deps: empty_dep(),
+                deps: deps.into(),
             },
             sub: Impl {
                 path: value.path.into(),
-                service: value.service.as_str().into(),
+                service,
+                socket_user: value.socket_user.map(Into::into),
+                socket_group: value.socket_group.map(Into::into),
+                socket_mode: value.socket_mode.unwrap_or(DEFAULT_SOCKET_MODE),
+                directory_mode: value.directory_mode.unwrap_or(DEFAULT_DIRECTORY_MODE),
             },
         }
     }
@@ -1,11 +1,30 @@
-use std::{mem, os::unix::net::UnixListener, path::Path};
+use std::{
+    fs::Permissions,
+    os::{
+        fd::{AsFd, OwnedFd},
+        unix::{
+            fs::{chown, FileTypeExt, PermissionsExt},
+            net::UnixListener,
+        },
+    },
+    path::Path,
+};
 
 use async_trait::async_trait;
-use tap::Tap;
-use tokio::{io::unix::AsyncFd, sync::oneshot};
+use tokio::{
+    fs::{self, DirBuilder},
+    io::{self, unix::AsyncFd},
+    sync::watch,
+};
 
-use super::{ChildStdio, Extra, RtMsg, Unit, UnitDeps, UnitHandle, UnitId, UnitImpl, UnitKind};
-use crate::Rc;
+use super::{
+    state_changed, Extra, RtMsg, StartError, State, Unit, UnitDeps, UnitHandle, UnitId, UnitImpl,
+    UnitKind,
+};
+use crate::{
+    util::user::{lookup_group, lookup_user},
+    Rc,
+};
 
 pub(crate) mod loader;
 
@@ -13,18 +32,67 @@ pub(crate) mod loader;
 pub(crate) struct Impl {
     path: Rc<Path>,
     service: UnitId,
+    socket_user: Option<Rc<str>>,
+    socket_group: Option<Rc<str>>,
+    socket_mode: u32,
+    directory_mode: u32,
+}
+
+impl Impl {
+    /// create the parent dirs on demand, bind the socket,
+    /// and apply the ownership and permission before anyone can use the fd \
+    /// the socket file is not left behind on errors, or the next bind fails
+    async fn bind(&self) -> io::Result<UnixListener> {
+        let uid = match &self.socket_user {
+            Some(user) => Some(lookup_user(user).await?),
+            None => None,
+        };
+        let gid = match &self.socket_group {
+            Some(group) => Some(lookup_group(group).await?),
+            None => None,
+        };
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(self.directory_mode)
+                .create(parent)
+                .await?;
+        }
+        // like systemd, a stale socket file of the last instance is removed first
+        match fs::symlink_metadata(&self.path).await {
+            Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(&self.path).await?,
+            _ => (),
+        }
+        let socket = UnixListener::bind(&self.path)?;
+        let result = async {
+            socket.set_nonblocking(true)?;
+            fs::set_permissions(&self.path, Permissions::from_mode(self.socket_mode)).await?;
+            if uid.is_some() || gid.is_some() {
+                chown(&self.path, uid, gid)?;
+            }
+            io::Result::Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            fs::remove_file(&self.path).await.ok();
+            return Err(e);
+        }
+        Ok(socket)
+    }
 }
 
 enum RtState {
     Listening,
-    Starting(oneshot::Receiver<ChildStdio>),
-    Running(ChildStdio),
+    /// the service is triggered and takes over the socket
+    Running,
 }
 
 pub(super) struct Handle {
     fd: AsyncFd<UnixListener>,
     rt_state: RtState,
     service: UnitId,
+    /// state of the triggered service
+    service_state: Option<watch::Receiver<State>>,
 }
 
 #[async_trait]
@@ -34,28 +102,34 @@ impl super::Handle for Handle {
     }
     async fn wait(&mut self) -> RtMsg {
         // todo: monitor socket state
-        match &mut self.rt_state {
+        match self.rt_state {
             RtState::Listening => {
                 let mut read_ready = self.fd.readable().await.unwrap();
                 read_ready.retain_ready();
-                let (s, r) = oneshot::channel();
-                self.rt_state = RtState::Starting(r);
-                RtMsg::TriggerStart(self.service.clone(), Extra { basic_io: Some(s) })
+                self.rt_state = RtState::Running;
+                // only the states since triggering tell when the service exits
+                if let Some(state) = &mut self.service_state {
+                    state.borrow_and_update();
+                }
+                RtMsg::TriggerStart(self.service.clone())
             }
-            RtState::Starting(_) => {
-                if let RtState::Starting(r) = mem::replace(&mut self.rt_state, RtState::Listening) {
-                    match r.await {
-                        Ok(child_stdio) => {
-                            self.rt_state = RtState::Running(child_stdio);
-                            RtMsg::Yield
-                        }
-                        Err(_) => todo!(),
-                    }
-                } else {
-                    unreachable!()
+            // listen again once the service is dead, the pending connections trigger it again
+            RtState::Running => {
+                if state_changed(&mut self.service_state).await.is_dead() {
+                    self.rt_state = RtState::Listening;
                 }
+                RtMsg::Yield
+            }
+        }
+    }
+
+    fn listen_fds(&self) -> Vec<OwnedFd> {
+        match self.fd.get_ref().as_fd().try_clone_to_owned() {
+            Ok(fd) => vec![fd],
+            Err(e) => {
+                println!("socket: failed to dup the fd: {}", e);
+                Vec::new()
             }
-            RtState::Running(_) => todo!(),
         }
     }
 }
@@ -82,15 +156,24 @@ impl Unit for UnitImpl<Impl> {
         self.common.deps.clone()
     }
 
-    async fn start(&self) -> Result<UnitHandle, ()> {
-        let socket = UnixListener::bind(&self.sub.path)
-            .unwrap()
-            .tap_mut(|s| s.set_nonblocking(true).unwrap());
+    async fn start(&self, extra: Extra) -> Result<UnitHandle, StartError> {
+        let socket = match self.sub.bind().await {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("{}: {}", self.name(), e);
+                return Err(StartError::default());
+            }
+        };
         let fd = AsyncFd::new(socket).unwrap();
         Ok(Box::new(Handle {
             fd,
             rt_state: RtState::Listening,
             service: self.sub.service.clone(),
+            service_state: extra
+                .triggers
+                .into_iter()
+                .find(|(id, _)| id == &self.sub.service)
+                .map(|(_, state)| state),
         }))
     }
 
@@ -98,8 +181,41 @@ impl Unit for UnitImpl<Impl> {
         handle.stop().await.or(Err(()))
     }
 
-    async fn restart(&self, handle: UnitHandle) -> Result<UnitHandle, ()> {
+    async fn restart(&self, handle: UnitHandle, extra: Extra) -> Result<UnitHandle, StartError> {
         self.stop(handle).await?;
-        self.start().await
+        self.start(extra).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Impl;
+
+    #[test]
+    fn test_bind() {
+        let dir = std::env::temp_dir().join(format!("sysrs-socket-{}", std::process::id()));
+        let path = dir.join("test.sock");
+        let socket = |user: Option<&str>| Impl {
+            path: path.as_path().into(),
+            service: "test.service".into(),
+            socket_user: user.map(Into::into),
+            socket_group: None,
+            socket_mode: 0o666,
+            directory_mode: 0o755,
+        };
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                // the stale socket file of the last instance is replaced
+                drop(socket(None).bind().await.unwrap());
+                drop(socket(None).bind().await.unwrap());
+                std::fs::remove_file(&path).unwrap();
+
+                assert!(socket(Some("no-such-user")).bind().await.is_err());
+                assert!(!path.exists());
+            });
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
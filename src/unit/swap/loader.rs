@@ -0,0 +1,80 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    unit::{UnitCommon, UnitDeps, UnitImpl},
+    util::loader::{empty_str, extend_unitids, str_to_mount_unitids, ExtraDeps},
+};
+
+use super::{add_device_deps, swap_mounts, Impl};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Swap {
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) requires: String,
+    #[serde(default)]
+    pub(crate) wants: String,
+    #[serde(default)]
+    pub(crate) before: String,
+    #[serde(default)]
+    pub(crate) after: String,
+    #[serde(default)]
+    pub(crate) conflicts: String,
+    #[serde(flatten)]
+    pub(crate) extra_deps: ExtraDeps,
+    /// space separated paths, whose mount units are required
+    #[serde(default)]
+    pub(crate) requires_mounts_for: String,
+    /// the swap device or file
+    pub(crate) what: PathBuf,
+    #[serde(default)]
+    pub(crate) priority: Option<i32>,
+    /// `once`, `pages`, or empty for both, see `swapon(8)`
+    #[serde(default)]
+    pub(crate) discard: Option<String>,
+}
+
+impl From<Swap> for UnitImpl<Impl> {
+    fn from(value: Swap) -> Self {
+        let Swap {
+            name,
+            requires,
+            wants,
+            before,
+            after,
+            conflicts,
+            extra_deps,
+            requires_mounts_for,
+            what,
+            priority,
+            discard,
+        } = value;
+
+        let mut deps = UnitDeps::from_strs(&requires, &wants, &before, &after, &conflicts)
+            .with_extra(&extra_deps);
+        deps.requires_mounts_for = str_to_mount_unitids(&requires_mounts_for);
+        // implicit deps: the swap file is on some mount
+        extend_unitids(&mut deps.requires_mounts_for, &swap_mounts(&what));
+        add_device_deps(&mut deps, &what);
+
+        Self {
+            common: UnitCommon {
+                name: name.into(),
+                description: empty_str(),
+                documentation: empty_str(),
+                deps: deps.into(),
+            },
+            sub: Impl {
+                what: what.into(),
+                priority,
+                discard: discard.map(Into::into),
+            },
+        }
+    }
+}
+
+pub(crate) fn load_swap(s: &str) -> UnitImpl<Impl> {
+    toml::from_str::<Swap>(s).unwrap().into()
+}
@@ -0,0 +1,253 @@
+use std::{fs::File, path::Path, process::Stdio};
+
+use async_trait::async_trait;
+use tokio::{
+    fs,
+    io::{self, unix::AsyncFd},
+    process::Command,
+};
+
+use super::{
+    Extra, RtMsg, StartError, State, Unit, UnitCommon, UnitDeps, UnitHandle, UnitId, UnitImpl,
+    UnitKind,
+};
+use crate::{
+    fstab::FsEntry,
+    unit::device::device_unit_id,
+    util::{
+        loader::{empty_str, extend_unitids, str_to_mount_unitids},
+        mount::{path_to_unit_name, proc_file_changed, watch_proc_file},
+    },
+    Rc,
+};
+
+pub(crate) mod loader;
+
+const PROC_SWAPS: &str = "/proc/swaps";
+
+#[derive(Debug, Clone)]
+pub(crate) struct Impl {
+    what: Rc<Path>,
+    priority: Option<i32>,
+    /// `Some("")` to discard with both policies
+    discard: Option<Rc<str>>,
+}
+
+/// mount units of the dir containing the swap file, devices need none
+fn swap_mounts(what: &Path) -> Box<[UnitId]> {
+    match what.parent() {
+        Some(dir) if !what.starts_with("/dev") => str_to_mount_unitids(dir.to_str().unwrap_or("")),
+        _ => Box::new([]),
+    }
+}
+
+/// the swap device should show up first
+fn add_device_deps(deps: &mut UnitDeps, what: &Path) {
+    if let Some(device) = device_unit_id(what) {
+        extend_unitids(&mut deps.requires, std::slice::from_ref(&device));
+        extend_unitids(&mut deps.after, &[device]);
+    }
+}
+
+/// whether the swap is listed in `/proc/swaps`, which contains the resolved paths
+async fn is_swap_active(what: &Path) -> bool {
+    let what = fs::canonicalize(what)
+        .await
+        .unwrap_or_else(|_| what.to_path_buf());
+    let Ok(swaps) = fs::read_to_string(PROC_SWAPS).await else {
+        return false;
+    };
+    swaps
+        .lines()
+        .skip(1) // header
+        .filter_map(|line| line.split_ascii_whitespace().next())
+        .any(|filename| Path::new(filename) == what)
+}
+
+/// watch `/proc/swaps` for swapoff done by others, like `MountMonitorStore` for mounts
+pub(super) struct Handle {
+    what: Rc<Path>,
+    swaps: AsyncFd<File>,
+}
+
+impl Handle {
+    fn new(what: Rc<Path>) -> io::Result<Self> {
+        Ok(Self {
+            what,
+            swaps: watch_proc_file(PROC_SWAPS)?,
+        })
+    }
+}
+
+#[async_trait]
+impl super::Handle for Handle {
+    async fn stop(self: Box<Self>) -> Result<(), UnitHandle> {
+        Ok(())
+    }
+
+    async fn wait(&mut self) -> RtMsg {
+        match proc_file_changed(&self.swaps).await {
+            Ok(()) if !is_swap_active(&self.what).await => RtMsg::Exit(State::Stopped),
+            Ok(()) => RtMsg::Yield,
+            Err(e) => {
+                println!("swap: failed to watch {}: {}", PROC_SWAPS, e);
+                RtMsg::Exit(State::Failed)
+            }
+        }
+    }
+}
+
+impl From<FsEntry> for UnitImpl<Impl> {
+    fn from(value: FsEntry) -> Self {
+        let what = value.mount_info.fs_spec.clone();
+        let options: Vec<&str> = value
+            .mount_info
+            .mount_options
+            .split(',')
+            .map(str::trim)
+            .collect();
+        let has = |option: &str| options.contains(&option);
+        let priority = options
+            .iter()
+            .find_map(|o| o.strip_prefix("pri=")?.parse().ok());
+        let discard = options.iter().find_map(|&o| match o {
+            "discard" => Some(Rc::from("")),
+            o => o.strip_prefix("discard=").map(Rc::from),
+        });
+
+        let mut deps = UnitDeps {
+            requires_mounts_for: swap_mounts(&what),
+            ..Default::default()
+        };
+        add_device_deps(&mut deps, &what);
+        if !has("noauto") {
+            let target = UnitId::from("swap.target");
+            if has("nofail") {
+                deps.wanted_by = Box::new([target]);
+            } else {
+                deps.required_by = Box::new([target.clone()]);
+                deps.before = Box::new([target]);
+            }
+        }
+
+        let name = path_to_unit_name(what.to_str().unwrap(), "swap");
+        Self {
+            common: UnitCommon {
+                name: name.into(),
+                description: empty_str(),
+                documentation: empty_str(),
+                deps: deps.into(),
+            },
+            sub: Impl {
+                what: what.into(),
+                priority,
+                discard,
+            },
+        }
+    }
+}
+
+async fn run(cmd: &mut Command) -> Result<(), ()> {
+    match cmd.stdin(Stdio::null()).status().await {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => {
+            println!("swap: {:?} exited with {}", cmd.as_std(), status);
+            Err(())
+        }
+        Err(e) => {
+            println!("swap: failed to run {:?}: {}", cmd.as_std(), e);
+            Err(())
+        }
+    }
+}
+
+#[async_trait]
+impl Unit for UnitImpl<Impl> {
+    fn name(&self) -> Rc<str> {
+        Rc::clone(&self.common.name)
+    }
+
+    fn description(&self) -> Rc<str> {
+        Rc::clone(&self.common.description)
+    }
+
+    fn documentation(&self) -> Rc<str> {
+        Rc::clone(&self.common.documentation)
+    }
+
+    fn kind(&self) -> UnitKind {
+        UnitKind::Swap
+    }
+
+    fn deps(&self) -> Rc<UnitDeps> {
+        self.common.deps.clone()
+    }
+
+    async fn start(&self, _: Extra) -> Result<UnitHandle, StartError> {
+        let Impl {
+            what,
+            priority,
+            discard,
+        } = &self.sub;
+        if !is_swap_active(what).await {
+            let mut cmd = Command::new("swapon");
+            if let Some(priority) = priority {
+                cmd.arg("--priority").arg(priority.to_string());
+            }
+            match discard.as_deref() {
+                Some("") => {
+                    cmd.arg("--discard");
+                }
+                Some(policy) => {
+                    cmd.arg(format!("--discard={}", policy));
+                }
+                None => (),
+            }
+            run(cmd.arg(what.as_ref())).await?;
+        }
+        match Handle::new(what.clone()) {
+            Ok(handle) => Ok(Box::new(handle)),
+            Err(e) => {
+                println!("swap: failed to watch {}: {}", PROC_SWAPS, e);
+                Err(StartError::default())
+            }
+        }
+    }
+
+    async fn stop(&self, handle: UnitHandle) -> Result<(), ()> {
+        handle.stop().await.or(Err(()))?;
+        if is_swap_active(&self.sub.what).await {
+            run(Command::new("swapoff").arg(self.sub.what.as_ref())).await?;
+        }
+        Ok(())
+    }
+
+    async fn restart(&self, handle: UnitHandle, extra: Extra) -> Result<UnitHandle, StartError> {
+        self.stop(handle).await?;
+        self.start(extra).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Impl;
+    use crate::{
+        fstab::FsEntry,
+        unit::{Unit, UnitId, UnitImpl},
+    };
+
+    #[test]
+    fn test_swap_from_fstab() {
+        let entry =
+            FsEntry::try_from("/var/swap-file none swap pri=10,discard=once,nofail 0 0").unwrap();
+        let swap = UnitImpl::<Impl>::from(entry);
+        assert_eq!(&*swap.name(), "var-swap\\x2dfile.swap");
+        assert_eq!(swap.sub.priority, Some(10));
+        assert_eq!(swap.sub.discard.as_deref(), Some("once"));
+        assert_eq!(&*swap.deps().wanted_by, &[UnitId::from("swap.target")]);
+        assert_eq!(
+            &*swap.deps().requires_mounts_for,
+            &[UnitId::from("var.mount"), UnitId::from("-.mount")]
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    unit::{UnitCommon, UnitDeps, UnitImpl, UnitKind},
+    util::loader::{default_true, empty_str, str_to_mount_unitids, ExtraDeps},
+};
+
+use super::{add_default_deps, Impl};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Target {
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) requires: String,
+    #[serde(default)]
+    pub(crate) wants: String,
+    #[serde(default)]
+    pub(crate) before: String,
+    #[serde(default)]
+    pub(crate) after: String,
+    #[serde(default)]
+    pub(crate) conflicts: String,
+    #[serde(flatten)]
+    pub(crate) extra_deps: ExtraDeps,
+    /// space separated paths, whose mount units are required
+    #[serde(default)]
+    pub(crate) requires_mounts_for: String,
+    /// implicit deps on the standard targets, see `add_default_deps`
+    #[serde(default = "default_true")]
+    pub(crate) default_dependencies: bool,
+}
+
+impl From<Target> for UnitImpl<Impl> {
+    fn from(value: Target) -> Self {
+        let mut deps = UnitDeps {
+            requires_mounts_for: str_to_mount_unitids(&value.requires_mounts_for),
+            ..UnitDeps::from_strs(
+                &value.requires,
+                &value.wants,
+                &value.before,
+                &value.after,
+                &value.conflicts,
+            )
+            .with_extra(&value.extra_deps)
+        };
+        if value.default_dependencies {
+            add_default_deps(&mut deps, UnitKind::Target);
+        }
+        Self {
+            common: UnitCommon {
+                name: value.name.into(),
+                description: empty_str(),
+                documentation: empty_str(),
+                deps: deps.into(),
+            },
+            sub: Impl {},
+        }
+    }
+}
+
+pub(crate) fn load_target(s: &str) -> UnitImpl<Impl> {
+    // dbg!(&t);
+    toml::from_str::<Target>(s).unwrap().into()
+    // dbg!(t)
+}
@@ -0,0 +1,197 @@
+use async_trait::async_trait;
+use futures::future::pending;
+
+use super::{
+    Extra, RtMsg, StartError, Unit, UnitCommon, UnitDeps, UnitHandle, UnitId, UnitImpl, UnitKind,
+};
+use crate::{
+    util::loader::{empty_str, extend_unitids},
+    Rc,
+};
+
+pub(crate) mod loader;
+
+pub(crate) const SYSINIT_TARGET: &str = "sysinit.target";
+pub(crate) const BASIC_TARGET: &str = "basic.target";
+pub(crate) const SOCKETS_TARGET: &str = "sockets.target";
+pub(crate) const TIMERS_TARGET: &str = "timers.target";
+pub(crate) const PATHS_TARGET: &str = "paths.target";
+pub(crate) const SHUTDOWN_TARGET: &str = "shutdown.target";
+
+/// `(name, description, requires, wants)` of the built-in targets, see `systemd.special(7)`, \
+/// each one is also after the units it pulls in, and has no default deps
+const STANDARD_TARGETS: &[(&str, &str, &[&str], &[&str])] = &[
+    ("local-fs.target", "Local File Systems", &[], &[]),
+    ("remote-fs.target", "Remote File Systems", &[], &[]),
+    ("swap.target", "Swaps", &[], &[]),
+    (
+        SYSINIT_TARGET,
+        "System Initialization",
+        &[],
+        &["local-fs.target", "swap.target"],
+    ),
+    (SOCKETS_TARGET, "Sockets", &[], &[]),
+    (TIMERS_TARGET, "Timers", &[], &[]),
+    (PATHS_TARGET, "Paths", &[], &[]),
+    (
+        BASIC_TARGET,
+        "Basic System",
+        &[SYSINIT_TARGET],
+        &[SOCKETS_TARGET, TIMERS_TARGET, PATHS_TARGET],
+    ),
+    (
+        "multi-user.target",
+        "Multi-User System",
+        &[BASIC_TARGET],
+        &[],
+    ),
+    (
+        "default.target",
+        "Default Target",
+        &["multi-user.target"],
+        &[],
+    ),
+    (SHUTDOWN_TARGET, "System Shutdown", &[], &[]),
+];
+
+fn unitids(names: &[&str]) -> Box<[UnitId]> {
+    names.iter().map(|&name| UnitId::from(name)).collect()
+}
+
+/// the built-in targets, overridden by the unit files with the same name
+pub(crate) fn standard_targets() -> impl Iterator<Item = UnitImpl<Impl>> {
+    STANDARD_TARGETS
+        .iter()
+        .map(|&(name, description, requires, wants)| UnitImpl {
+            common: UnitCommon {
+                name: name.into(),
+                description: description.into(),
+                documentation: empty_str(),
+                deps: UnitDeps {
+                    requires: unitids(requires),
+                    wants: unitids(wants),
+                    after: unitids(&[requires, wants].concat()),
+                    ..Default::default()
+                }
+                .into(),
+            },
+            sub: Impl,
+        })
+}
+
+/// implicit deps on the standard targets, disabled by `default_dependencies = false`; \
+/// mounts, swaps and devices get theirs from fstab instead
+pub(crate) fn add_default_deps(deps: &mut UnitDeps, kind: UnitKind) {
+    let (requires, after, before): (&[&str], &[&str], &[&str]) = match kind {
+        UnitKind::Service => (
+            &[SYSINIT_TARGET],
+            &[SYSINIT_TARGET, BASIC_TARGET],
+            &[SHUTDOWN_TARGET],
+        ),
+        UnitKind::Socket => (
+            &[SYSINIT_TARGET],
+            &[SYSINIT_TARGET],
+            &[SOCKETS_TARGET, SHUTDOWN_TARGET],
+        ),
+        UnitKind::Timer => (
+            &[SYSINIT_TARGET],
+            &[SYSINIT_TARGET],
+            &[TIMERS_TARGET, SHUTDOWN_TARGET],
+        ),
+        UnitKind::Path => (
+            &[SYSINIT_TARGET],
+            &[SYSINIT_TARGET],
+            &[PATHS_TARGET, SHUTDOWN_TARGET],
+        ),
+        UnitKind::Target => {
+            // a target waits for the units it pulls in
+            let pulled = [&*deps.requires, &*deps.wants].concat();
+            extend_unitids(&mut deps.after, &pulled);
+            (&[], &[], &[SHUTDOWN_TARGET])
+        }
+        UnitKind::Mount | UnitKind::Swap | UnitKind::Device => return,
+    };
+    extend_unitids(&mut deps.requires, &unitids(requires));
+    extend_unitids(&mut deps.after, &unitids(after));
+    extend_unitids(&mut deps.before, &unitids(before));
+    extend_unitids(&mut deps.conflicts, &unitids(&[SHUTDOWN_TARGET]));
+}
+
+#[derive(Debug)]
+pub(crate) struct Impl;
+pub(super) struct Handle;
+#[async_trait]
+impl super::Handle for Handle {
+    async fn stop(self: Box<Self>) -> Result<(), UnitHandle> {
+        Ok(())
+    }
+    async fn wait(&mut self) -> RtMsg {
+        pending().await
+    }
+}
+
+#[async_trait]
+impl Unit for UnitImpl<Impl> {
+    fn name(&self) -> Rc<str> {
+        Rc::clone(&self.common.name)
+    }
+
+    fn description(&self) -> Rc<str> {
+        Rc::clone(&self.common.description)
+    }
+
+    fn documentation(&self) -> Rc<str> {
+        Rc::clone(&self.common.documentation)
+    }
+
+    fn kind(&self) -> UnitKind {
+        UnitKind::Target
+    }
+
+    fn deps(&self) -> Rc<UnitDeps> {
+        self.common.deps.clone()
+    }
+
+    async fn start(&self, _: Extra) -> Result<UnitHandle, StartError> {
+        Ok(Box::new(Handle))
+    }
+
+    async fn stop(&self, handle: UnitHandle) -> Result<(), ()> {
+        Ok(())
+    }
+
+    async fn restart(&self, handle: UnitHandle, _: Extra) -> Result<UnitHandle, StartError> {
+        Ok(Box::new(Handle))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::unit::{service::loader::load_service, target::loader::load_target, Unit, UnitId};
+
+    fn ids(names: &[&str]) -> Box<[UnitId]> {
+        names.iter().map(|&n| UnitId::from(n)).collect()
+    }
+
+    #[test]
+    fn test_default_deps() {
+        let service = load_service("name = \"a.service\"\nkind = \"Simple\"\nstart = \"true\"");
+        let d = service.deps();
+        assert_eq!(d.requires, ids(&["sysinit.target"]));
+        assert_eq!(d.after, ids(&["sysinit.target", "basic.target"]));
+        assert_eq!(d.before, ids(&["shutdown.target"]));
+        assert_eq!(d.conflicts, ids(&["shutdown.target"]));
+
+        let service = load_service(
+            "name = \"a.service\"\nkind = \"Simple\"\nstart = \"true\"\ndefault_dependencies = false",
+        );
+        assert!(service.deps().requires.is_empty());
+        assert!(service.deps().conflicts.is_empty());
+
+        let target = load_target("name = \"a.target\"\nwants = \"a.service\"");
+        let d = target.deps();
+        assert_eq!(d.requires, ids(&[]));
+        assert_eq!(d.after, ids(&["a.service"]));
+        assert_eq!(d.conflicts, ids(&["shutdown.target"]));
+    }
+}
@@ -0,0 +1,361 @@
+//! calendar events, see `systemd.time(7)`
+//!
+//! `[DayOfWeek] [Year-Month-Day] [Hour:Minute[:Second]] [UTC]`
+
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike,
+    Utc, Weekday,
+};
+
+/// how many years to look ahead before giving up
+const MAX_YEARS_AHEAD: i32 = 200;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Error {
+    Weekday(Box<str>),
+    Date(Box<str>),
+    Time(Box<str>),
+    Value(Box<str>),
+    Trailing(Box<str>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Weekday(s) => write!(f, "invalid weekday `{}`", s),
+            Error::Date(s) => write!(f, "invalid date `{}`", s),
+            Error::Time(s) => write!(f, "invalid time `{}`", s),
+            Error::Value(s) => write!(f, "invalid value `{}`", s),
+            Error::Trailing(s) => write!(f, "unexpected `{}`", s),
+        }
+    }
+}
+
+/// `start..end/step`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Range {
+    start: u32,
+    end: u32,
+    step: u32,
+}
+
+/// a component of the calendar event, `None` for `*`, which matches everything
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Component(Option<Box<[Range]>>);
+
+impl Component {
+    fn any() -> Self {
+        Self(None)
+    }
+
+    fn value(v: u32) -> Self {
+        Self(Some(Box::new([Range {
+            start: v,
+            end: v,
+            step: 1,
+        }])))
+    }
+
+    fn matches(&self, v: u32) -> bool {
+        match &self.0 {
+            None => true,
+            Some(ranges) => ranges
+                .iter()
+                .any(|r| r.start <= v && v <= r.end && (v - r.start).is_multiple_of(r.step)),
+        }
+    }
+
+    /// parse `*`, `a`, `a..b`, `a/step`, `*/step` or lists of them, like `1,3..5,10/2`
+    fn parse(s: &str, min: u32, max: u32) -> Result<Self, Error> {
+        if s == "*" {
+            return Ok(Self::any());
+        }
+        let err = || Error::Value(s.into());
+        let num = |s: &str| -> Result<u32, Error> {
+            let v = s.parse().map_err(|_| err())?;
+            if (min..=max).contains(&v) {
+                Ok(v)
+            } else {
+                Err(err())
+            }
+        };
+        s.split(',')
+            .map(|item| {
+                let (base, step) = match item.split_once('/') {
+                    Some((base, step)) => (base, Some(step.parse().map_err(|_| err())?)),
+                    None => (item, None),
+                };
+                let (start, end) = match base.split_once("..") {
+                    _ if base == "*" => (min, max),
+                    Some((start, end)) => (num(start)?, num(end)?),
+                    None if step.is_some() => (num(base)?, max),
+                    None => (num(base)?, num(base)?),
+                };
+                match step {
+                    Some(0) => Err(err()),
+                    _ if start > end => Err(err()),
+                    step => Ok(Range {
+                        start,
+                        end,
+                        step: step.unwrap_or(1),
+                    }),
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(|ranges| Self(Some(ranges)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CalendarSpec {
+    /// bitmask, `1 << 0` for monday
+    weekdays: u8,
+    year: Component,
+    month: Component,
+    day: Component,
+    hour: Component,
+    minute: Component,
+    second: Component,
+    utc: bool,
+}
+
+fn parse_weekday(s: &str) -> Result<u32, Error> {
+    Weekday::from_str(s)
+        .map(|d| d.num_days_from_monday())
+        .map_err(|_| Error::Weekday(s.into()))
+}
+
+fn parse_weekdays(s: &str) -> Result<u8, Error> {
+    let mut mask = 0;
+    for item in s.split(',') {
+        let (start, end) = match item.split_once("..") {
+            Some((start, end)) => (parse_weekday(start)?, parse_weekday(end)?),
+            None => (parse_weekday(item)?, parse_weekday(item)?),
+        };
+        if start > end {
+            return Err(Error::Weekday(item.into()));
+        }
+        for d in start..=end {
+            mask |= 1 << d;
+        }
+    }
+    Ok(mask)
+}
+
+fn shorthand(s: &str) -> Option<&'static str> {
+    Some(match s {
+        "minutely" => "*-*-* *:*:00",
+        "hourly" => "*-*-* *:00:00",
+        "daily" => "*-*-* 00:00:00",
+        "weekly" => "Mon *-*-* 00:00:00",
+        "monthly" => "*-*-01 00:00:00",
+        "yearly" | "annually" => "*-01-01 00:00:00",
+        "quarterly" => "*-01,04,07,10-01 00:00:00",
+        "semiannually" => "*-01,07-01 00:00:00",
+        _ => return None,
+    })
+}
+
+impl FromStr for CalendarSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words: Vec<&str> = s.split_whitespace().collect();
+        let utc = words.last().is_some_and(|w| w.eq_ignore_ascii_case("utc"));
+        if utc {
+            words.pop();
+        }
+        if let [word] = words.as_slice() {
+            if let Some(expanded) = shorthand(&word.to_ascii_lowercase()) {
+                return Ok(Self {
+                    utc,
+                    ..expanded.parse()?
+                });
+            }
+        }
+
+        let mut words = words.into_iter().peekable();
+        let weekdays = match words.next_if(|w| w.starts_with(|c: char| c.is_ascii_alphabetic())) {
+            Some(w) => parse_weekdays(w)?,
+            None => 0x7f,
+        };
+
+        let (mut year, mut month, mut day) = (Component::any(), Component::any(), Component::any());
+        if let Some(date) = words.next_if(|w| w.contains('-')) {
+            let parts: Vec<&str> = date.split('-').collect();
+            let (y, m, d) = match parts.as_slice() {
+                [y, m, d] => (*y, *m, *d),
+                [m, d] => ("*", *m, *d),
+                _ => return Err(Error::Date(date.into())),
+            };
+            year = Component::parse(y, 1970, 2199)?;
+            month = Component::parse(m, 1, 12)?;
+            day = Component::parse(d, 1, 31)?;
+        }
+
+        // time defaults to `00:00:00`
+        let (mut hour, mut minute, mut second) = (
+            Component::value(0),
+            Component::value(0),
+            Component::value(0),
+        );
+        if let Some(time) = words.next_if(|w| w.contains(':')) {
+            let parts: Vec<&str> = time.split(':').collect();
+            let (h, m, s) = match parts.as_slice() {
+                [h, m, s] => (*h, *m, *s),
+                [h, m] => (*h, *m, "00"),
+                _ => return Err(Error::Time(time.into())),
+            };
+            hour = Component::parse(h, 0, 23)?;
+            minute = Component::parse(m, 0, 59)?;
+            second = Component::parse(s, 0, 59)?;
+        }
+
+        if let Some(w) = words.next() {
+            return Err(Error::Trailing(w.into()));
+        }
+
+        Ok(Self {
+            weekdays,
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            utc,
+        })
+    }
+}
+
+impl CalendarSpec {
+    fn date_matches(&self, date: NaiveDate) -> bool {
+        self.day.matches(date.day())
+            && self.weekdays & (1 << date.weekday().num_days_from_monday()) != 0
+    }
+
+    /// the first naive time matching the spec, which is later than `after`
+    fn next_naive(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let next_day = |t: NaiveDateTime| t.date().succ_opt().map(|d| d.and_time(NaiveTime::MIN));
+        let mut t = after.with_nanosecond(0)? + Duration::seconds(1);
+        let max_year = after.year() + MAX_YEARS_AHEAD;
+        loop {
+            if t.year() > max_year {
+                return None;
+            }
+            if !self.year.matches(t.year() as u32) {
+                t = NaiveDate::from_ymd_opt(t.year() + 1, 1, 1)?.and_time(NaiveTime::MIN);
+            } else if !self.month.matches(t.month()) {
+                let (y, m) = match t.month() {
+                    12 => (t.year() + 1, 1),
+                    m => (t.year(), m + 1),
+                };
+                t = NaiveDate::from_ymd_opt(y, m, 1)?.and_time(NaiveTime::MIN);
+            } else if !self.date_matches(t.date()) {
+                t = next_day(t)?;
+            } else if !self.hour.matches(t.hour()) {
+                t = match t.hour() {
+                    23 => next_day(t)?,
+                    h => t.date().and_hms_opt(h + 1, 0, 0)?,
+                };
+            } else if !self.minute.matches(t.minute()) {
+                t = t.with_second(0)? + Duration::minutes(1);
+            } else if !self.second.matches(t.second()) {
+                t += Duration::seconds(1);
+            } else {
+                return Some(t);
+            }
+        }
+    }
+
+    fn next_in<Tz: TimeZone>(&self, tz: &Tz, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut naive = after.with_timezone(tz).naive_local();
+        loop {
+            naive = self.next_naive(naive)?;
+            // skip the time not existing due to DST changes
+            if let Some(t) = tz.from_local_datetime(&naive).earliest() {
+                let t = t.with_timezone(&Utc);
+                if t > after {
+                    return Some(t);
+                }
+            }
+        }
+    }
+
+    /// the next elapse of the calendar event, which is later than `after`
+    pub(crate) fn next_elapse(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.utc {
+            self.next_in(&Utc, after)
+        } else {
+            self.next_in(&Local, after)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::{CalendarSpec, Error};
+
+    fn utc(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, s).unwrap()
+    }
+
+    fn next(spec: &str, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        format!("{} UTC", spec)
+            .parse::<CalendarSpec>()
+            .unwrap()
+            .next_elapse(after)
+    }
+
+    #[test]
+    fn test_shorthand() {
+        let now = utc(2023, 12, 31, 23, 30, 15);
+        assert_eq!(next("minutely", now), Some(utc(2023, 12, 31, 23, 31, 0)));
+        assert_eq!(next("hourly", now), Some(utc(2024, 1, 1, 0, 0, 0)));
+        assert_eq!(next("daily", now), Some(utc(2024, 1, 1, 0, 0, 0)));
+        // 2024-01-01 is monday
+        assert_eq!(next("weekly", now), Some(utc(2024, 1, 1, 0, 0, 0)));
+        assert_eq!(next("quarterly", now), Some(utc(2024, 1, 1, 0, 0, 0)));
+        assert_eq!(
+            next("monthly", utc(2024, 1, 1, 0, 0, 0)),
+            Some(utc(2024, 2, 1, 0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn test_expressions() {
+        let now = utc(2023, 9, 20, 10, 7, 0); // wednesday
+        assert_eq!(next("*:0/15", now), Some(utc(2023, 9, 20, 10, 15, 0)));
+        assert_eq!(next("Mon..Fri 03:00", now), Some(utc(2023, 9, 21, 3, 0, 0)));
+        assert_eq!(
+            next("Sat,Sun *-*-* 12:00", now),
+            Some(utc(2023, 9, 23, 12, 0, 0))
+        );
+        assert_eq!(next("*-02-29 00:00", now), Some(utc(2024, 2, 29, 0, 0, 0)));
+        assert_eq!(
+            next("2023-*-01,15 08:30:10", now),
+            Some(utc(2023, 10, 1, 8, 30, 10))
+        );
+        assert_eq!(next("2022-01-01", now), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            "Moon 12:00".parse::<CalendarSpec>(),
+            Err(Error::Weekday("Moon".into()))
+        );
+        assert_eq!(
+            "*-13-01".parse::<CalendarSpec>(),
+            Err(Error::Value("13".into()))
+        );
+        assert_eq!(
+            "12:00 now".parse::<CalendarSpec>(),
+            Err(Error::Trailing("now".into()))
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use std::{path::Path, time::Duration};
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+
+use crate::{
+    unit::{target::add_default_deps, UnitCommon, UnitDeps, UnitId, UnitImpl, UnitKind},
+    util::loader::{
+        default_true, deserialize_timespan, empty_str, extend_unitids, str_to_mount_unitids,
+        ExtraDeps,
+    },
+};
+
+use super::{calendar::CalendarSpec, Impl};
+
+/// default accuracy of the timer, same as systemd
+const DEFAULT_ACCURACY: Duration = Duration::from_secs(60);
+/// where the last trigger time of persistent timers is recorded
+const STAMP_DIR: &str = "/var/lib/sysrs/timers";
+
+fn deserialize_calendar<'de, D: Deserializer<'de>>(d: D) -> Result<Option<CalendarSpec>, D::Error> {
+    Option::<String>::deserialize(d)?
+        .map(|s| s.parse().map_err(D::Error::custom))
+        .transpose()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Timer {
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) requires: String,
+    #[serde(default)]
+    pub(crate) wants: String,
+    #[serde(default)]
+    pub(crate) before: String,
+    #[serde(default)]
+    pub(crate) after: String,
+    #[serde(default)]
+    pub(crate) conflicts: String,
+    #[serde(flatten)]
+    pub(crate) extra_deps: ExtraDeps,
+    /// space separated paths, whose mount units are required
+    #[serde(default)]
+    pub(crate) requires_mounts_for: String,
+    /// the unit to start when the timer elapses, default to the service with the same name
+    #[serde(default)]
+    pub(crate) unit: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_timespan")]
+    pub(crate) on_boot_sec: Option<Duration>,
+    #[serde(default, deserialize_with = "deserialize_timespan")]
+    pub(crate) on_active_sec: Option<Duration>,
+    #[serde(default, deserialize_with = "deserialize_timespan")]
+    pub(crate) on_unit_active_sec: Option<Duration>,
+    #[serde(default, deserialize_with = "deserialize_timespan")]
+    pub(crate) on_unit_inactive_sec: Option<Duration>,
+    #[serde(skip_serializing, default, deserialize_with = "deserialize_calendar")]
+    pub(crate) on_calendar: Option<CalendarSpec>,
+    #[serde(default, deserialize_with = "deserialize_timespan")]
+    pub(crate) accuracy_sec: Option<Duration>,
+    #[serde(default, deserialize_with = "deserialize_timespan")]
+    pub(crate) randomized_delay_sec: Option<Duration>,
+    /// record the last trigger time, and catch up the missed `on_calendar` elapse
+    #[serde(default)]
+    pub(crate) persistent: bool,
+    /// implicit deps on the standard targets, see `add_default_deps`
+    #[serde(default = "default_true")]
+    pub(crate) default_dependencies: bool,
+}
+
+impl From<Timer> for UnitImpl<Impl> {
+    fn from(value: Timer) -> Self {
+        let Timer {
+            name,
+            requires,
+            wants,
+            before,
+            after,
+            conflicts,
+            extra_deps,
+            requires_mounts_for,
+            unit,
+            on_boot_sec,
+            on_active_sec,
+            on_unit_active_sec,
+            on_unit_inactive_sec,
+            on_calendar,
+            accuracy_sec,
+            randomized_delay_sec,
+            persistent,
+            default_dependencies,
+        } = value;
+
+        let stamp = persistent.then(|| Path::new(STAMP_DIR).join(format!("stamp-{}", name)).into());
+
+        let unit = match unit {
+            Some(unit) => UnitId::from(unit.as_str()),
+            None => {
+                let (stem, _) = name.rsplit_once('.').unwrap();
+                UnitId::from(format!("{}.service", stem).as_str())
+            }
+        };
+        let mut deps = UnitDeps::from_strs(&requires, &wants, &before, &after, &conflicts)
+            .with_extra(&extra_deps);
+        deps.requires_mounts_for = str_to_mount_unitids(&requires_mounts_for);
+        // implicit deps: the timer triggers the unit
+        extend_unitids(&mut deps.before, std::slice::from_ref(&unit));
+        deps.triggers = Box::new([unit.clone()]);
+        if default_dependencies {
+            add_default_deps(&mut deps, UnitKind::Timer);
+        }
+
+        Self {
+            common: UnitCommon {
+                name: name.into(),
+                description: empty_str(),
+                documentation: empty_str(),
+                deps: deps.into(),
+            },
+            sub: Impl {
+                unit,
+                on_boot_sec,
+                on_active_sec,
+                on_unit_active_sec,
+                on_unit_inactive_sec,
+                on_calendar,
+                accuracy_sec: accuracy_sec.unwrap_or(DEFAULT_ACCURACY),
+                randomized_delay_sec: randomized_delay_sec.unwrap_or_default(),
+                stamp,
+            },
+        }
+    }
+}
+
+pub(crate) fn load_timer(s: &str) -> UnitImpl<Impl> {
+    toml::from_str::<Timer>(s).unwrap().into()
+}
@@ -0,0 +1,265 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    path::Path,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::future::pending;
+use tokio::{
+    fs, select,
+    sync::watch,
+    time::{sleep_until, Instant},
+};
+
+use self::calendar::CalendarSpec;
+use super::{
+    state_changed, Extra, RtMsg, StartError, State, Unit, UnitDeps, UnitHandle, UnitId, UnitImpl,
+    UnitKind,
+};
+use crate::{util::time::boot_time, Rc};
+
+pub(crate) mod calendar;
+pub(crate) mod loader;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Impl {
+    unit: UnitId,
+    on_boot_sec: Option<Duration>,
+    on_active_sec: Option<Duration>,
+    on_unit_active_sec: Option<Duration>,
+    on_unit_inactive_sec: Option<Duration>,
+    on_calendar: Option<CalendarSpec>,
+    accuracy_sec: Duration,
+    randomized_delay_sec: Duration,
+    /// stamp file recording the last trigger time, for persistent timers
+    stamp: Option<Rc<Path>>,
+}
+
+/// runtime info of the timer, for `list-timers`
+#[derive(Debug, Clone)]
+pub(crate) struct TimerInfo {
+    pub next: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    pub unit: UnitId,
+}
+
+async fn read_stamp(stamp: &Path) -> Option<DateTime<Utc>> {
+    let s = fs::read_to_string(stamp).await.ok()?;
+    match DateTime::parse_from_rfc3339(s.trim()) {
+        Ok(t) => Some(t.with_timezone(&Utc)),
+        Err(e) => {
+            println!("timer: invalid stamp file {:?}: {}", stamp, e);
+            None
+        }
+    }
+}
+
+async fn write_stamp(stamp: &Path, t: DateTime<Utc>) {
+    if let Some(dir) = stamp.parent() {
+        fs::create_dir_all(dir).await.ok();
+    }
+    if let Err(e) = fs::write(stamp, t.to_rfc3339()).await {
+        println!("timer: failed to write stamp file {:?}: {}", stamp, e);
+    }
+}
+
+pub(super) struct Handle {
+    timer: Impl,
+    boot: Instant,
+    activated: Instant,
+    last_trigger: Option<Instant>,
+    last_trigger_realtime: Option<DateTime<Utc>>,
+    /// state of the triggered unit
+    unit_state: Option<watch::Receiver<State>>,
+    last_active: Option<Instant>,
+    last_inactive: Option<Instant>,
+    next: Option<Instant>,
+    /// rolled once per elapse, so that recalculating the elapse doesn't move it
+    random_delay: Duration,
+}
+
+impl Handle {
+    fn new(
+        timer: Impl,
+        unit_state: Option<watch::Receiver<State>>,
+        last_trigger_realtime: Option<DateTime<Utc>>,
+    ) -> Self {
+        let now = Instant::now();
+        let state = unit_state.as_ref().map(|s| *s.borrow()).unwrap_or_default();
+        let mut handle = Self {
+            timer,
+            boot: boot_time(),
+            activated: now,
+            last_trigger: None,
+            last_trigger_realtime,
+            unit_state,
+            last_active: state.is_active().then_some(now),
+            last_inactive: matches!(state, State::Stopped | State::Failed).then_some(now),
+            next: None,
+            random_delay: Duration::ZERO,
+        };
+        handle.random_delay = random_delay(handle.timer.randomized_delay_sec);
+        handle.next = handle.next_elapse();
+        handle
+    }
+
+    /// the earliest elapse of all the triggers
+    fn next_elapse(&self) -> Option<Instant> {
+        let Impl {
+            on_boot_sec,
+            on_active_sec,
+            on_unit_active_sec,
+            on_unit_inactive_sec,
+            on_calendar,
+            ..
+        } = &self.timer;
+        let monotonic = [
+            on_boot_sec.map(|d| self.boot + d),
+            on_active_sec.map(|d| self.activated + d),
+            on_unit_active_sec.zip(self.last_active).map(|(d, t)| t + d),
+            on_unit_inactive_sec
+                .zip(self.last_inactive)
+                .map(|(d, t)| t + d),
+        ]
+        .into_iter()
+        .flatten()
+        // elapses before the last trigger are already handled
+        .filter(|&t| self.last_trigger.is_none_or(|last| t > last));
+        // todo: recalculate when the wall clock changes
+        let calendar = on_calendar.as_ref().and_then(|spec| {
+            let now = Utc::now();
+            // an elapse missed since the last trigger will elapse immediately
+            let after = self.last_trigger_realtime.map_or(now, |last| last.min(now));
+            let elapse = spec.next_elapse(after)?;
+            Some(Instant::now() + (elapse - now).to_std().unwrap_or_default())
+        });
+        let elapse = monotonic.chain(calendar).min()?;
+        Some(self.coalesce(elapse) + self.random_delay)
+    }
+
+    /// delay the elapse to the next multiple of accuracy since boot,
+    /// so that timers elapsing around the same time can be coalesced
+    fn coalesce(&self, elapse: Instant) -> Instant {
+        let accuracy = self.timer.accuracy_sec.as_nanos();
+        if accuracy == 0 {
+            return elapse;
+        }
+        let since_boot = elapse.saturating_duration_since(self.boot).as_nanos();
+        let rounded = since_boot.div_ceil(accuracy) * accuracy;
+        self.boot + Duration::from_nanos(rounded as u64)
+    }
+}
+
+fn random_delay(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    // `RandomState` is randomly seeded, which is good enough to spread the timers
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_nanos(random % max.as_nanos() as u64)
+}
+
+async fn sleep_until_elapse(elapse: Option<Instant>) {
+    match elapse {
+        Some(elapse) => sleep_until(elapse).await,
+        None => pending().await,
+    }
+}
+
+#[async_trait]
+impl super::Handle for Handle {
+    async fn stop(self: Box<Self>) -> Result<(), UnitHandle> {
+        Ok(())
+    }
+
+    async fn wait(&mut self) -> RtMsg {
+        select! {
+            _ = sleep_until_elapse(self.next) => {
+                let now = Utc::now();
+                self.last_trigger = Some(Instant::now());
+                self.last_trigger_realtime = Some(now);
+                if let Some(stamp) = &self.timer.stamp {
+                    write_stamp(stamp, now).await;
+                }
+                self.random_delay = random_delay(self.timer.randomized_delay_sec);
+                self.next = self.next_elapse();
+                RtMsg::TriggerStart(self.timer.unit.clone())
+            }
+            state = state_changed(&mut self.unit_state) => {
+                let now = Instant::now();
+                if state.is_active() {
+                    self.last_active = Some(now);
+                } else if matches!(state, State::Stopped | State::Failed) {
+                    self.last_inactive = Some(now);
+                }
+                self.next = self.next_elapse();
+                RtMsg::Yield
+            }
+        }
+    }
+
+    fn timer_info(&self) -> Option<TimerInfo> {
+        let next = self.next.map(|next| {
+            let left = next.saturating_duration_since(Instant::now());
+            Utc::now()
+                + chrono::Duration::from_std(left).unwrap_or_else(|_| chrono::Duration::zero())
+        });
+        Some(TimerInfo {
+            next,
+            last: self.last_trigger_realtime,
+            unit: self.timer.unit.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Unit for UnitImpl<Impl> {
+    fn name(&self) -> Rc<str> {
+        Rc::clone(&self.common.name)
+    }
+
+    fn description(&self) -> Rc<str> {
+        Rc::clone(&self.common.description)
+    }
+
+    fn documentation(&self) -> Rc<str> {
+        Rc::clone(&self.common.documentation)
+    }
+
+    fn kind(&self) -> UnitKind {
+        UnitKind::Timer
+    }
+
+    fn deps(&self) -> Rc<UnitDeps> {
+        self.common.deps.clone()
+    }
+
+    async fn start(&self, extra: Extra) -> Result<UnitHandle, StartError> {
+        let unit_state = extra
+            .triggers
+            .into_iter()
+            .find(|(id, _)| id == &self.sub.unit)
+            .map(|(_, state)| state);
+        let last_trigger = match &self.sub.stamp {
+            Some(stamp) => read_stamp(stamp).await,
+            None => None,
+        };
+        Ok(Box::new(Handle::new(
+            self.sub.clone(),
+            unit_state,
+            last_trigger,
+        )))
+    }
+
+    async fn stop(&self, handle: UnitHandle) -> Result<(), ()> {
+        handle.stop().await.or(Err(()))
+    }
+
+    async fn restart(&self, handle: UnitHandle, extra: Extra) -> Result<UnitHandle, StartError> {
+        self.stop(handle).await?;
+        self.start(extra).await
+    }
+}
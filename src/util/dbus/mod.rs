@@ -1,48 +1,81 @@
-use std::time::Duration;
+use std::path::Path;
 
-use tokio::{sync::mpsc::Sender, time::sleep};
-use zbus::{dbus_interface, Connection, ConnectionBuilder};
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc::Sender;
+use zbus::{dbus_interface, fdo, Connection, ConnectionBuilder};
 
 use crate::{
     actor::{
-        state::{self, print_state},
+        dep::transaction::JobKind,
+        guard::{self, list_timers},
+        state::{self, get_state, print_state},
         unit::{
             self,
-            utils::{print_store, start_unit, stop_unit},
+            utils::{enqueue_job, list_units, print_store, reload_unit, restart_job},
         },
     },
-    unit::{State, UnitId},
+    fstab::{FsEntry, FSTAB},
+    unit::{JobMode, UnitId},
 };
 
 #[derive(Debug)]
 pub(crate) struct DbusServer {
     store: Sender<unit::Message>,
     state: Sender<state::Message>,
+    guard: Sender<guard::Message>,
 }
 
 impl DbusServer {
-    pub(crate) fn new(store: Sender<unit::Message>, state: Sender<state::Message>) -> Self {
-        Self { store, state }
+    pub(crate) fn new(
+        store: Sender<unit::Message>,
+        state: Sender<state::Message>,
+        guard: Sender<guard::Message>,
+    ) -> Self {
+        Self {
+            store,
+            state,
+            guard,
+        }
     }
 }
+impl DbusServer {
+    /// todo: wait the job to finish, and then get the result
+    async fn enqueue(&self, unit: &str, kind: JobKind, mode: &str) -> fdo::Result<u64> {
+        let mode: JobMode = mode.parse().map_err(fdo::Error::InvalidArgs)?;
+        enqueue_job(&self.store, UnitId::from(unit), kind, mode)
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+}
+
 #[dbus_interface(name = "org.sysrs.sysrs1")]
 impl DbusServer {
     fn echo(&self, msg: &str) -> String {
         println!("dbus: called echo with `{msg}`");
         msg.to_owned()
     }
-    async fn start_unit(&self, unit: &str) {
-        let id = UnitId::from(unit);
-        start_unit(&self.store, id.clone()).await;
-        // todo: really wait unit change to starting and then get the result
-        sleep(Duration::from_millis(10)).await;
+    /// the id of the job enqueued, like `replace`, `fail` of systemctl `--job-mode`
+    async fn start_unit(&self, unit: &str, mode: &str) -> fdo::Result<u64> {
+        self.enqueue(unit, JobKind::Start, mode).await
+    }
+
+    async fn stop_unit(&self, unit: &str, mode: &str) -> fdo::Result<u64> {
+        self.enqueue(unit, JobKind::Stop, mode).await
+    }
+
+    async fn isolate_unit(&self, unit: &str) -> fdo::Result<u64> {
+        self.enqueue(unit, JobKind::Start, "isolate").await
+    }
+
+    /// the id of the stop job, or of the start job if the unit is not running
+    async fn restart_unit(&self, unit: &str) -> fdo::Result<u64> {
+        restart_job(&self.store, UnitId::from(unit))
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
     }
 
-    async fn stop_unit(&self, unit: &str) {
-        let id = UnitId::from(unit);
-        stop_unit(&self.store, id.clone()).await;
-        // todo: really wait unit change to stop and then get the result
-        sleep(Duration::from_millis(10)).await;
+    async fn reload_unit(&self, unit: &str) {
+        reload_unit(&self.store, UnitId::from(unit)).await;
     }
 
     async fn print_store(&self) {
@@ -52,6 +85,47 @@ impl DbusServer {
         print_state(&self.state).await
     }
 
+    /// `(timer, next elapse, last trigger, unit to activate)` of the running timers \
+    /// time in usec since epoch, 0 if not available
+    async fn list_timers(&self) -> Vec<(String, u64, u64, String)> {
+        let usec = |t: Option<DateTime<Utc>>| t.map_or(0, |t| t.timestamp_micros() as u64);
+        list_timers(&self.guard)
+            .await
+            .into_iter()
+            .map(|(id, info)| {
+                (
+                    id.to_string(),
+                    usec(info.next),
+                    usec(info.last),
+                    info.unit.to_string(),
+                )
+            })
+            .collect()
+    }
+
+    /// `(unit, state, description)` of all the loaded units
+    async fn list_units(&self) -> Vec<(String, String, String)> {
+        let mut units = Vec::new();
+        for unit in list_units(&self.store).await {
+            let id = UnitId::from(unit.as_ref());
+            let state = get_state(&self.state, id.clone()).await;
+            units.push((
+                id.to_string(),
+                state.to_string(),
+                unit.description().to_string(),
+            ));
+        }
+        units
+    }
+
+    /// errors of the configuration, i.e. the invalid lines of fstab
+    async fn verify(&self) -> Vec<String> {
+        match FsEntry::read(Path::new(FSTAB)).await {
+            Ok((_, errors)) => errors.iter().map(ToString::to_string).collect(),
+            Err(e) => vec![format!("{}: {}", FSTAB, e)],
+        }
+    }
+
     fn get_unit(&self, unit: &str) {
         todo!()
     }
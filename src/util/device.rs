@@ -0,0 +1,186 @@
+use std::{
+    collections::{HashMap, HashSet},
+    mem::size_of,
+    os::fd::{AsRawFd, OwnedFd},
+    path::{Path, PathBuf},
+};
+
+use rustix::net::{netlink, recv, socket_with, AddressFamily, RecvFlags, SocketFlags, SocketType};
+use tokio::io::{unix::AsyncFd, Interest};
+
+pub(crate) const SYSFS: &str = "/sys";
+
+/// kernel uevents, and the ones sent by udev after the links like `/dev/disk/by-uuid` created
+const UEVENT_GROUPS: u32 = 1 | 2;
+
+/// the header of the uevents sent by udev, followed by a binary header
+const LIBUDEV_MAGIC: &[u8] = b"libudev\0";
+
+/// the device nodes in the sysfs tree, from `DEVNAME` of `<sysfs>/class/*/*/uevent`
+pub(crate) fn enumerate_devices(sysfs: &Path) -> HashSet<PathBuf> {
+    let Ok(classes) = std::fs::read_dir(sysfs.join("class")) else {
+        return HashSet::new();
+    };
+    classes
+        .flatten()
+        .filter_map(|class| std::fs::read_dir(class.path()).ok())
+        .flatten()
+        .flatten()
+        .filter_map(|dev| std::fs::read_to_string(dev.path().join("uevent")).ok())
+        .filter_map(|uevent| {
+            uevent
+                .lines()
+                .find_map(|line| line.strip_prefix("DEVNAME="))
+                .map(devname_to_node)
+        })
+        .collect()
+}
+
+/// `DEVNAME` is relative to `/dev` in the kernel uevents, but absolute in the udev ones
+fn devname_to_node(devname: &str) -> PathBuf {
+    Path::new("/dev").join(devname)
+}
+
+/// the device node a link like `/dev/disk/by-uuid/...` points to, or the node itself
+pub(crate) fn resolve_node(node: &Path) -> PathBuf {
+    std::fs::canonicalize(node).unwrap_or_else(|_| node.to_path_buf())
+}
+
+/// whether the device node, or the device a link like `/dev/disk/by-uuid/...` points to, exists
+pub(crate) fn is_device_present(sysfs: &Path, node: &Path) -> bool {
+    enumerate_devices(sysfs).contains(&resolve_node(node))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Uevent {
+    /// `add`, `remove`, `change`...
+    pub action: Box<str>,
+    pub node: Option<PathBuf>,
+}
+
+impl Uevent {
+    /// parse the `KEY=value` properties of a kernel or udev uevent message
+    pub(crate) fn parse(buf: &[u8]) -> Option<Self> {
+        let properties = match buf.strip_prefix(LIBUDEV_MAGIC) {
+            // magic, header size, properties offset...
+            Some(header) => {
+                let offset = u32::from_ne_bytes(header.get(8..12)?.try_into().ok()?);
+                buf.get(offset as usize..)?
+            }
+            // `add@/devices/...` first
+            None => buf,
+        };
+        let properties: HashMap<&str, &str> = properties
+            .split(|&b| b == 0)
+            .filter_map(|p| std::str::from_utf8(p).ok()?.split_once('='))
+            .collect();
+        Some(Self {
+            action: (*properties.get("ACTION")?).into(),
+            node: properties.get("DEVNAME").map(|&n| devname_to_node(n)),
+        })
+    }
+}
+
+/// a socket receiving the uevents, see `netlink(7)`
+pub(crate) struct UeventSocket {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl UeventSocket {
+    pub(crate) fn new() -> std::io::Result<Self> {
+        let fd = socket_with(
+            AddressFamily::NETLINK,
+            SocketType::DGRAM,
+            SocketFlags::CLOEXEC | SocketFlags::NONBLOCK,
+            Some(netlink::KOBJECT_UEVENT),
+        )?;
+        // rustix can't bind netlink sockets yet
+        // SAFETY: `sockaddr_nl` is plain data, and the size is given
+        let ret = unsafe {
+            let mut addr: libc::sockaddr_nl = std::mem::zeroed();
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = UEVENT_GROUPS;
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self {
+            fd: AsyncFd::with_interest(fd, Interest::READABLE)?,
+        })
+    }
+
+    /// the next valid uevent
+    pub(crate) async fn recv(&self) -> std::io::Result<Uevent> {
+        let mut buf = [0; 8192];
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let len = match guard.try_io(|fd| Ok(recv(fd, &mut buf, RecvFlags::empty())?)) {
+                Ok(len) => len?,
+                Err(_would_block) => continue,
+            };
+            if let Some(uevent) = Uevent::parse(&buf[..len]) {
+                return Ok(uevent);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::{enumerate_devices, Uevent};
+
+    #[test]
+    fn test_enumerate_devices() {
+        let sysfs = std::env::temp_dir().join(format!("sysrs-sysfs-{}", std::process::id()));
+        let add = |class: &str, dev: &str, uevent: &str| {
+            let dir = sysfs.join("class").join(class).join(dev);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("uevent"), uevent).unwrap();
+        };
+        add(
+            "block",
+            "sda1",
+            "MAJOR=8\nMINOR=1\nDEVNAME=sda1\nDEVTYPE=partition\n",
+        );
+        add("tty", "ttyS0", "MAJOR=4\nMINOR=64\nDEVNAME=ttyS0\n");
+        add("net", "lo", "INTERFACE=lo\nIFINDEX=1\n");
+        let devices = enumerate_devices(&sysfs);
+        std::fs::remove_dir_all(&sysfs).unwrap();
+        assert_eq!(devices.len(), 2);
+        assert!(devices.contains(Path::new("/dev/sda1")));
+        assert!(devices.contains(Path::new("/dev/ttyS0")));
+    }
+
+    #[test]
+    fn test_parse_uevent() {
+        let kernel = b"add@/devices/pci0000:00/block/sda/sda1\0ACTION=add\0DEVPATH=/devices/pci0000:00/block/sda/sda1\0SUBSYSTEM=block\0DEVNAME=sda1\0";
+        assert_eq!(
+            Uevent::parse(kernel),
+            Some(Uevent {
+                action: "add".into(),
+                node: Some(PathBuf::from("/dev/sda1")),
+            })
+        );
+
+        let properties = b"ACTION=remove\0DEVNAME=/dev/sdb\0";
+        let mut udev = b"libudev\0".to_vec();
+        udev.extend(0xfeedcafe_u32.to_be_bytes());
+        udev.extend(40_u32.to_ne_bytes()); // header size
+        udev.extend(40_u32.to_ne_bytes()); // properties offset
+        udev.extend((properties.len() as u32).to_ne_bytes());
+        udev.resize(40, 0);
+        udev.extend(properties);
+        let uevent = Uevent::parse(&udev).unwrap();
+        assert_eq!(&*uevent.action, "remove");
+        assert_eq!(uevent.node, Some(PathBuf::from("/dev/sdb")));
+
+        assert_eq!(Uevent::parse(b"libudev\0"), None);
+    }
+}
@@ -1,15 +1,25 @@
-use std::{path::Path, sync::OnceLock};
+use std::{path::Path, sync::OnceLock, time::Duration};
 
-use futures::{Stream, StreamExt};
+use futures::{stream, Stream, StreamExt};
 use rustix::path::Arg;
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
 use tap::Pipe;
 use tokio::fs;
 
 use crate::{
+    fstab::{FsEntry, MountInfo},
     unit::{
-        service::loader::load_service, socket::loader::load_socket, target::loader::load_target,
-        Unit, UnitDeps, UnitId,
+        device::{Impl as DeviceImpl, DEVICE_TIMEOUT},
+        mount::{fsck::fsck_units, mount_device_unit_id, Impl as MountImpl},
+        path::loader::load_path,
+        service::loader::load_service,
+        socket::loader::load_socket,
+        swap::{loader::load_swap, Impl as SwapImpl},
+        target::loader::load_target,
+        timer::loader::load_timer,
+        JobMode, Unit, UnitDeps, UnitId, UnitImpl,
     },
+    util::{mount::mount_point_to_unit_name, time::parse_timespan},
     Rc,
 };
 
@@ -21,14 +31,176 @@ pub(crate) fn str_to_unitids(s: &str) -> Box<[UnitId]> {
         .collect()
 }
 
+/// mount units of the space separated paths and all their prefixes, \
+/// e.g. `/srv/db` to `srv-db.mount`, `srv.mount` and `-.mount`
+pub(crate) fn str_to_mount_unitids(s: &str) -> Box<[UnitId]> {
+    let mut ids: Vec<UnitId> = Vec::new();
+    for path in s.split_ascii_whitespace().map(Path::new) {
+        for prefix in path.ancestors().filter_map(|p| p.to_str()) {
+            let id = UnitId::from(mount_point_to_unit_name(prefix).as_str());
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+    }
+    ids.into()
+}
+
+/// the less common dep types, flattened into the unit files
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct ExtraDeps {
+    #[serde(default)]
+    pub(crate) binds_to: String,
+    #[serde(default)]
+    pub(crate) part_of: String,
+    #[serde(default)]
+    pub(crate) requisite: String,
+    #[serde(default)]
+    pub(crate) upholds: String,
+    #[serde(default)]
+    pub(crate) propagates_reload_to: String,
+    #[serde(default)]
+    pub(crate) on_failure: String,
+    #[serde(default)]
+    pub(crate) on_success: String,
+    #[serde(default)]
+    pub(crate) on_failure_job_mode: JobMode,
+}
+
+impl UnitDeps {
+    pub(crate) fn with_extra(self, extra: &ExtraDeps) -> Self {
+        Self {
+            binds_to: str_to_unitids(&extra.binds_to),
+            part_of: str_to_unitids(&extra.part_of),
+            requisite: str_to_unitids(&extra.requisite),
+            upholds: str_to_unitids(&extra.upholds),
+            propagates_reload_to: str_to_unitids(&extra.propagates_reload_to),
+            on_failure: str_to_unitids(&extra.on_failure),
+            on_success: str_to_unitids(&extra.on_success),
+            on_failure_job_mode: extra.on_failure_job_mode,
+            ..self
+        }
+    }
+}
+
+/// append implicit deps to the ones from unit file
+pub(crate) fn extend_unitids(ids: &mut Box<[UnitId]>, extra: &[UnitId]) {
+    *ids = ids.iter().chain(extra).cloned().collect();
+}
+
+/// deserialize an octal file mode, like `"0660"`
+pub(crate) fn deserialize_mode<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u32>, D::Error> {
+    Option::<String>::deserialize(d)?
+        .map(|s| u32::from_str_radix(s.trim(), 8).map_err(D::Error::custom))
+        .transpose()
+}
+
+/// deserialize a time span, like `"1h 30min"`
+pub(crate) fn deserialize_timespan<'de, D: Deserializer<'de>>(
+    d: D,
+) -> Result<Option<Duration>, D::Error> {
+    Option::<String>::deserialize(d)?
+        .map(|s| parse_timespan(&s).map_err(D::Error::custom))
+        .transpose()
+}
+
+/// for the bool fields on by default, like `default_dependencies`
+pub(crate) fn default_true() -> bool {
+    true
+}
+
 static EMPTYSTR: OnceLock<Rc<str>> = OnceLock::new();
 pub(crate) fn empty_str() -> Rc<str> {
     EMPTYSTR.get_or_init(|| ("".into())).clone()
 }
 
-static EMPTYDEP: OnceLock<Rc<UnitDeps>> = OnceLock::new();
-pub(crate) fn empty_dep() -> Rc<UnitDeps> {
-    EMPTYDEP.get_or_init(|| UnitDeps::default().into()).clone()
+const DEFAULT_TARGET: &str = "default.target";
+const PROC_CMDLINE: &str = "/proc/cmdline";
+
+/// the unit to boot into, overridden by `systemd.unit=` or `sysrs.unit=` in the kernel cmdline
+fn default_target(cmdline: &str) -> UnitId {
+    let unit = cmdline
+        .split_ascii_whitespace()
+        .filter_map(|arg| {
+            arg.strip_prefix("systemd.unit=")
+                .or_else(|| arg.strip_prefix("sysrs.unit="))
+        })
+        // the last one wins
+        .next_back()
+        .unwrap_or(DEFAULT_TARGET);
+    UnitId::from(unit)
+}
+
+pub(crate) async fn read_default_target() -> UnitId {
+    default_target(&fs::read_to_string(PROC_CMDLINE).await.unwrap_or_default())
+}
+
+/// how long to wait for the device of the entry, see `x-systemd.device-timeout=` in `systemd.mount(5)`
+fn device_timeout(mount_info: &MountInfo) -> Duration {
+    mount_info
+        .mount_options
+        .split(',')
+        .filter_map(|o| o.trim().strip_prefix("x-systemd.device-timeout="))
+        .find_map(|timeout| parse_timespan(timeout).ok())
+        .unwrap_or(DEVICE_TIMEOUT)
+}
+
+/// the fstab generator: a swap unit for every swap entry, and a mount unit for every other
+pub(crate) async fn load_units_from_fstab(
+    path: impl AsRef<Path>,
+) -> impl Stream<Item = Rc<dyn Unit + Send + Sync + 'static>> {
+    let path = path.as_ref();
+    let (swaps, entries): (Vec<FsEntry>, Vec<FsEntry>) = match FsEntry::read(path).await {
+        Ok((entries, errors)) => {
+            // the invalid lines are skipped, see `sysrsctl verify`
+            for e in errors {
+                println!("fstab: ignoring {}", e);
+            }
+            entries
+                .into_iter()
+                .partition(|entry| entry.mount_info.vfs_type.as_ref() == "swap")
+        }
+        Err(e) => {
+            println!("fstab: failed to open {:?}: {}", path, e);
+            Default::default()
+        }
+    };
+    let mount_points: Vec<&Path> = entries
+        .iter()
+        .map(|entry| &*entry.mount_info.mount_point)
+        .collect();
+    // the devices of the mounts, the swaps and the fscks, waited for `x-systemd.device-timeout=`
+    let mut devices: Vec<(&Path, Duration)> = entries
+        .iter()
+        .chain(swaps.iter())
+        .filter(|entry| mount_device_unit_id(&entry.mount_info).is_some())
+        .map(|entry| {
+            (
+                &*entry.mount_info.fs_spec,
+                device_timeout(&entry.mount_info),
+            )
+        })
+        .collect();
+    devices.sort_by_key(|&(node, _)| node);
+    devices.dedup_by_key(|&mut (node, _)| node);
+    let devices: Vec<_> = devices
+        .into_iter()
+        .map(|(node, timeout)| Rc::new(UnitImpl::<DeviceImpl>::new(node, timeout)) as _)
+        .collect();
+    let swaps = swaps
+        .into_iter()
+        .map(|entry| Rc::new(UnitImpl::<SwapImpl>::from(entry)) as _);
+    let fscks = fsck_units(&entries)
+        .into_iter()
+        .map(|fsck| Rc::new(fsck) as _);
+    let units: Vec<_> = entries
+        .iter()
+        .map(|entry| Rc::new(UnitImpl::<MountImpl>::from_fstab(entry, &mount_points)) as _)
+        .chain(fscks)
+        .chain(swaps)
+        .chain(devices)
+        .collect();
+    stream::iter(units)
 }
 
 pub(crate) async fn load_units_from_dir(
@@ -50,6 +222,9 @@ pub(crate) async fn load_units_from_dir(
                                 Some(Rc::new(f.await.ok()?.pipe_as_ref(load_service)) as _)
                             }
                             "socket" => Some(Rc::new(f.await.ok()?.pipe_as_ref(load_socket)) as _),
+                            "timer" => Some(Rc::new(f.await.ok()?.pipe_as_ref(load_timer)) as _),
+                            "path" => Some(Rc::new(f.await.ok()?.pipe_as_ref(load_path)) as _),
+                            "swap" => Some(Rc::new(f.await.ok()?.pipe_as_ref(load_swap)) as _),
                             _ => None,
                         }
                     } else {
@@ -63,3 +238,36 @@ pub(crate) async fn load_units_from_dir(
         todo!()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{default_target, device_timeout};
+    use crate::{
+        fstab::FsEntry,
+        unit::{device::DEVICE_TIMEOUT, UnitId},
+    };
+
+    #[test]
+    fn test_default_target() {
+        assert_eq!(
+            default_target("root=/dev/sda1 ro quiet"),
+            UnitId::from("default.target")
+        );
+        assert_eq!(
+            default_target("systemd.unit=rescue.target ro sysrs.unit=multi-user.target"),
+            UnitId::from("multi-user.target")
+        );
+    }
+
+    #[test]
+    fn test_device_timeout() {
+        let timeout = |line: &str| device_timeout(&FsEntry::try_from(line).unwrap().mount_info);
+        assert_eq!(timeout("/dev/sdb1 /data ext4 defaults"), DEVICE_TIMEOUT);
+        assert_eq!(
+            timeout("/dev/sdb1 /data ext4 nofail,x-systemd.device-timeout=5s"),
+            Duration::from_secs(5)
+        );
+    }
+}
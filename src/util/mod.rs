@@ -1,4 +1,7 @@
 pub(crate) mod dbus;
+pub(crate) mod device;
 pub(crate) mod event;
 pub(crate) mod loader;
 pub(crate) mod mount;
+pub(crate) mod time;
+pub(crate) mod user;
@@ -1,24 +1,213 @@
 use rustix::{
     fs::{mount as _mount, unmount as _unmount, MountFlags, UnmountFlags},
     io,
+    mount::{mount_change, mount_remount, MountPropagationFlags},
 };
 
+use std::{fs::File, path::Path};
+
+use tokio::io::{unix::AsyncFd, Interest};
+
 use crate::{fstab::MountInfo, Rc};
 
+/// mount options of fstab split into the generic vfs flags and the fs specific data, see `mount(8)`
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct MountOptions {
+    pub flags: MountFlags,
+    /// `shared`, `slave`..., changed by another `mount(2)` after mounting
+    pub propagation: MountPropagationFlags,
+    /// change the options of the mounted filesystem, rather than mounting it
+    pub remount: bool,
+    pub data: String,
+}
+
+/// `(option, flags to set, flags to clear)`
+const FLAG_OPTIONS: &[(&str, MountFlags, MountFlags)] = &[
+    ("ro", MountFlags::RDONLY, MountFlags::empty()),
+    ("rw", MountFlags::empty(), MountFlags::RDONLY),
+    ("nosuid", MountFlags::NOSUID, MountFlags::empty()),
+    ("suid", MountFlags::empty(), MountFlags::NOSUID),
+    ("nodev", MountFlags::NODEV, MountFlags::empty()),
+    ("dev", MountFlags::empty(), MountFlags::NODEV),
+    ("noexec", MountFlags::NOEXEC, MountFlags::empty()),
+    ("exec", MountFlags::empty(), MountFlags::NOEXEC),
+    ("sync", MountFlags::SYNCHRONOUS, MountFlags::empty()),
+    ("async", MountFlags::empty(), MountFlags::SYNCHRONOUS),
+    ("dirsync", MountFlags::DIRSYNC, MountFlags::empty()),
+    ("noatime", MountFlags::NOATIME, MountFlags::empty()),
+    ("atime", MountFlags::empty(), MountFlags::NOATIME),
+    ("nodiratime", MountFlags::NODIRATIME, MountFlags::empty()),
+    ("diratime", MountFlags::empty(), MountFlags::NODIRATIME),
+    ("relatime", MountFlags::RELATIME, MountFlags::empty()),
+    ("norelatime", MountFlags::empty(), MountFlags::RELATIME),
+    ("strictatime", MountFlags::STRICTATIME, MountFlags::empty()),
+    (
+        "nostrictatime",
+        MountFlags::empty(),
+        MountFlags::STRICTATIME,
+    ),
+    ("lazytime", MountFlags::LAZYTIME, MountFlags::empty()),
+    ("nolazytime", MountFlags::empty(), MountFlags::LAZYTIME),
+    (
+        "mand",
+        MountFlags::PERMIT_MANDATORY_FILE_LOCKING,
+        MountFlags::empty(),
+    ),
+    (
+        "nomand",
+        MountFlags::empty(),
+        MountFlags::PERMIT_MANDATORY_FILE_LOCKING,
+    ),
+    ("silent", MountFlags::SILENT, MountFlags::empty()),
+    ("loud", MountFlags::empty(), MountFlags::SILENT),
+    ("nosymfollow", MountFlags::NOSYMFOLLOW, MountFlags::empty()),
+    ("symfollow", MountFlags::empty(), MountFlags::NOSYMFOLLOW),
+    ("bind", MountFlags::BIND, MountFlags::empty()),
+    (
+        "rbind",
+        MountFlags::BIND.union(MountFlags::REC),
+        MountFlags::empty(),
+    ),
+];
+
+const PROPAGATION_OPTIONS: &[(&str, MountPropagationFlags)] = &[
+    ("shared", MountPropagationFlags::SHARED),
+    ("slave", MountPropagationFlags::SLAVE),
+    ("private", MountPropagationFlags::PRIVATE),
+    ("unbindable", MountPropagationFlags::UNBINDABLE),
+    (
+        "rshared",
+        MountPropagationFlags::SHARED.union(MountPropagationFlags::REC),
+    ),
+    (
+        "rslave",
+        MountPropagationFlags::SLAVE.union(MountPropagationFlags::REC),
+    ),
+    (
+        "rprivate",
+        MountPropagationFlags::PRIVATE.union(MountPropagationFlags::REC),
+    ),
+    (
+        "runbindable",
+        MountPropagationFlags::UNBINDABLE.union(MountPropagationFlags::REC),
+    ),
+];
+
+/// options only meaningful to userspace (fstab, mount helpers), never passed to the kernel
+fn is_userspace_option(option: &str) -> bool {
+    let name = option.split_once('=').map_or(option, |(name, _)| name);
+    matches!(
+        name,
+        "defaults"
+            | "auto"
+            | "noauto"
+            | "nofail"
+            | "user"
+            | "nouser"
+            | "users"
+            | "owner"
+            | "group"
+            | "comment"
+            | "_netdev"
+    ) || name.starts_with("x-")
+}
+
+impl From<&str> for MountOptions {
+    fn from(value: &str) -> Self {
+        let mut flags = MountFlags::empty();
+        let mut propagation = MountPropagationFlags::empty();
+        let mut remount = false;
+        let mut data = Vec::new();
+        for option in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            if let Some((_, set, clear)) = FLAG_OPTIONS.iter().find(|(name, ..)| *name == option) {
+                flags = (flags - *clear) | *set;
+            } else if let Some((_, p)) =
+                PROPAGATION_OPTIONS.iter().find(|(name, _)| *name == option)
+            {
+                propagation = *p;
+            } else if option == "remount" {
+                remount = true;
+            } else if !is_userspace_option(option) {
+                data.push(option);
+            }
+        }
+        Self {
+            flags,
+            propagation,
+            remount,
+            data: data.join(","),
+        }
+    }
+}
+
+/// the per mount point flags ignored by the kernel when creating a bind mount
+const BIND_REMOUNT_FLAGS: MountFlags = MountFlags::RDONLY
+    .union(MountFlags::NOSUID)
+    .union(MountFlags::NODEV)
+    .union(MountFlags::NOEXEC);
+
+/// the flags of the second `mount(2)` with `MS_REMOUNT | MS_BIND` applying them to a new bind mount, \
+/// `None` if not a bind mount or no such flags, see `mount(8)`
+fn bind_remount_flags(flags: MountFlags) -> Option<MountFlags> {
+    let remount_flags = flags & BIND_REMOUNT_FLAGS;
+    (flags.contains(MountFlags::BIND) && !remount_flags.is_empty())
+        .then_some(MountFlags::BIND | remount_flags)
+}
+
+/// mount the filesystem, or change the options of it with `remount`, \
+/// then change the propagation type if given
 pub(crate) fn mount(mount_info: Rc<MountInfo>, flags: MountFlags) -> io::Result<()> {
     let MountInfo {
         fs_spec: source,
         mount_point: target,
         vfs_type,
-        mount_options: data,
+        mount_options,
     } = mount_info.as_ref();
+    let options = MountOptions::from(mount_options.as_ref());
+    if options.remount {
+        return remount(mount_info, flags);
+    }
+    let flags = flags | options.flags;
     _mount(
         source.as_ref(),
         target.as_ref(),
         vfs_type.as_ref(),
         flags,
-        data.as_ref(),
-    )
+        options.data.as_str(),
+    )?;
+    let result = match bind_remount_flags(flags) {
+        Some(flags) => mount_remount(target.as_ref(), flags, ""),
+        None => Ok(()),
+    }
+    .and_then(|()| change_propagation(target, options.propagation));
+    // don't leave it mounted half configured, the unit fails and the next start mounts it again
+    if result.is_err() {
+        _unmount(target.as_ref(), UnmountFlags::DETACH).ok();
+    }
+    result
+}
+
+/// apply the options to the mounted filesystem, including the bind mounts with `bind`
+pub(crate) fn remount(mount_info: Rc<MountInfo>, flags: MountFlags) -> io::Result<()> {
+    let MountInfo {
+        mount_point: target,
+        mount_options,
+        ..
+    } = mount_info.as_ref();
+    let options = MountOptions::from(mount_options.as_ref());
+    mount_remount(
+        target.as_ref(),
+        flags | options.flags,
+        options.data.as_str(),
+    )?;
+    change_propagation(target, options.propagation)
+}
+
+fn change_propagation(target: &Path, propagation: MountPropagationFlags) -> io::Result<()> {
+    if propagation.is_empty() {
+        return Ok(());
+    }
+    mount_change(target, propagation)
 }
 
 pub(crate) fn unmount(mount_info: Rc<MountInfo>, flags: UnmountFlags) -> io::Result<()> {
@@ -26,47 +215,387 @@ pub(crate) fn unmount(mount_info: Rc<MountInfo>, flags: UnmountFlags) -> io::Res
     _unmount(mount_point.as_ref(), flags)
 }
 
-#[derive(Debug, Clone)]
+pub(crate) const PROC_MOUNTINFO: &str = "/proc/self/mountinfo";
+
+/// open a proc file like `/proc/self/mountinfo` or `/proc/swaps`, \
+/// which becomes priority-ready (`POLLPRI`) when the mounts or swaps change, see `proc(5)`
+pub(crate) fn watch_proc_file(path: &str) -> std::io::Result<AsyncFd<File>> {
+    AsyncFd::with_interest(File::open(path)?, Interest::PRIORITY)
+}
+
+/// wait until the watched proc file changes
+pub(crate) async fn proc_file_changed(fd: &AsyncFd<File>) -> std::io::Result<()> {
+    fd.ready(Interest::PRIORITY).await?.clear_ready();
+    Ok(())
+}
+
+/// a line of `/proc/self/mountinfo`, see `proc(5)`
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct ProcMountInfoLine {
-    // mount_id:
-    // parent_id:
-    // st_dev:
-    // root:
+    pub mount_id: u32,
+    pub parent_id: u32,
+    /// `major:minor` of the device
+    pub st_dev: (u32, u32),
+    /// root of the mount within the filesystem, e.g. the source dir of bind mounts
+    pub root: Rc<str>,
     pub mount_point: Rc<str>,
     pub mount_options: Rc<str>,
-    // optional_fields
+    /// like `shared:1`, `master:2`
+    pub optional_fields: Box<[Rc<str>]>,
     pub fs_type: Rc<str>,
     pub mount_source: Rc<str>,
-    // super_option:
+    pub super_options: Rc<str>,
+}
+
+/// decode the `\040`-style octal escapes for space, tab, newline and backslash
+pub(crate) fn unescape(s: &str) -> Rc<str> {
+    if !s.contains('\\') {
+        return s.into();
+    }
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .filter(|_| bytes[i] == b'\\')
+            .and_then(|o| std::str::from_utf8(o).ok())
+            .and_then(|o| u8::from_str_radix(o, 8).ok());
+        match octal {
+            Some(b) => {
+                decoded.push(b);
+                i += 4;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into()
 }
 
 impl ProcMountInfoLine {
-    pub(crate) fn parse(s: &str) -> Self {
-        // let s: [&str; 4];
-        let mut iter = s.trim().split_ascii_whitespace().skip(4);
-        let mount_point = iter.next().unwrap().into();
-        let mount_options = iter.next().unwrap().into();
-        let mut iter = iter.skip_while(|&w| w == "-");
-        let fs_type = iter.next().unwrap().into();
-        let mount_source = iter.next().unwrap().into();
-        Self {
+    /// `36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue`
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        let mut iter = s.split_ascii_whitespace();
+        let mount_id = iter.next()?.parse().ok()?;
+        let parent_id = iter.next()?.parse().ok()?;
+        let (major, minor) = iter.next()?.split_once(':')?;
+        let st_dev = (major.parse().ok()?, minor.parse().ok()?);
+        let root = unescape(iter.next()?);
+        let mount_point = unescape(iter.next()?);
+        let mount_options = iter.next()?.into();
+        let optional_fields = iter
+            .by_ref()
+            .take_while(|&w| w != "-")
+            .map(Into::into)
+            .collect();
+        let fs_type = iter.next()?.into();
+        let mount_source = unescape(iter.next()?);
+        let super_options = iter.next().unwrap_or("").into();
+        Some(Self {
+            mount_id,
+            parent_id,
+            st_dev,
+            root,
             mount_point,
             mount_options,
+            optional_fields,
             fs_type,
             mount_source,
+            super_options,
+        })
+    }
+}
+
+/// a snapshot of the mounts
+#[derive(Debug, Default, Clone)]
+pub(crate) struct MountTable {
+    /// in the order of mounting, so the later ones are on top of the former ones
+    mounts: Box<[ProcMountInfoLine]>,
+}
+
+/// changes between two snapshots of the mount table
+#[derive(Debug, Default)]
+pub(crate) struct MountDiff {
+    pub added: Vec<ProcMountInfoLine>,
+    pub removed: Vec<ProcMountInfoLine>,
+    /// `(old, new)` of the mounts whose options or sources changed, like after remounting
+    pub changed: Vec<(ProcMountInfoLine, ProcMountInfoLine)>,
+}
+
+impl MountDiff {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl MountTable {
+    pub(crate) fn parse(s: &str) -> Self {
+        let mounts = s
+            .lines()
+            .filter_map(|line| {
+                let mount = ProcMountInfoLine::parse(line);
+                if mount.is_none() {
+                    println!("mountinfo: invalid line `{}`", line);
+                }
+                mount
+            })
+            .collect();
+        Self { mounts }
+    }
+
+    pub(crate) async fn read() -> std::io::Result<Self> {
+        Ok(Self::parse(
+            &tokio::fs::read_to_string(PROC_MOUNTINFO).await?,
+        ))
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &ProcMountInfoLine> {
+        self.mounts.iter()
+    }
+
+    pub(crate) fn get(&self, mount_id: u32) -> Option<&ProcMountInfoLine> {
+        self.mounts.iter().find(|m| m.mount_id == mount_id)
+    }
+
+    /// the top mount on the mount point
+    pub(crate) fn find(&self, mount_point: impl AsRef<Path>) -> Option<&ProcMountInfoLine> {
+        let mount_point = mount_point.as_ref();
+        self.mounts
+            .iter()
+            .rev()
+            .find(|m| Path::new(m.mount_point.as_ref()) == mount_point)
+    }
+
+    pub(crate) fn is_mounted(&self, mount_point: impl AsRef<Path>) -> bool {
+        self.find(mount_point).is_some()
+    }
+
+    pub(crate) fn children<'a>(
+        &'a self,
+        mount: &'a ProcMountInfoLine,
+    ) -> impl Iterator<Item = &'a ProcMountInfoLine> {
+        self.mounts
+            .iter()
+            .filter(move |m| m.parent_id == mount.mount_id && m.mount_id != mount.mount_id)
+    }
+
+    /// what happened from `self` to `new`, the mounts are identified by mount id
+    pub(crate) fn diff(&self, new: &Self) -> MountDiff {
+        let mut diff = MountDiff::default();
+        for old in self.iter() {
+            match new.get(old.mount_id) {
+                None => diff.removed.push(old.clone()),
+                Some(m) if m != old => diff.changed.push((old.clone(), m.clone())),
+                Some(_) => (),
+            }
         }
+        diff.added = new
+            .iter()
+            .filter(|m| self.get(m.mount_id).is_none())
+            .cloned()
+            .collect();
+        diff
     }
 }
 
-pub(crate) fn mount_point_to_unit_name(name: &str) -> String {
-    (if let Some(s) = name.strip_prefix('/') {
-        if s.is_empty() {
-            String::from('-')
-        } else {
-            s.replace('-', "\\x2d").replace('/', "-")
+/// the api file systems are kept mounted at shutdown, same as systemd
+const API_MOUNT_POINTS: [&str; 4] = ["/proc", "/sys", "/dev", "/run"];
+
+fn is_api_mount(mount_point: &str) -> bool {
+    let mount_point = Path::new(mount_point);
+    API_MOUNT_POINTS.iter().any(|p| mount_point.starts_with(p))
+}
+
+/// try a normal unmount first, if failed, remount it read-only to keep the data safe, \
+/// then force and detach it
+fn shutdown_unmount(mount_point: &str) -> bool {
+    match _unmount(mount_point, UnmountFlags::empty()) {
+        Ok(_) => {
+            println!("umount: unmounted {}", mount_point);
+            return true;
+        }
+        Err(e) => println!("umount: failed to unmount {}: {}", mount_point, e),
+    }
+    match mount_remount(mount_point, MountFlags::RDONLY, "") {
+        Ok(_) => println!("umount: remounted {} read-only", mount_point),
+        Err(e) => println!("umount: failed to remount {} read-only: {}", mount_point, e),
+    }
+    for flags in [UnmountFlags::FORCE, UnmountFlags::DETACH] {
+        match _unmount(mount_point, flags) {
+            Ok(_) => {
+                println!("umount: unmounted {} with {:?}", mount_point, flags);
+                return true;
+            }
+            Err(e) => println!(
+                "umount: failed to unmount {} with {:?}: {}",
+                mount_point, flags, e
+            ),
+        }
+    }
+    false
+}
+
+/// the last step of shutdown: unmount all the file systems except the root and the api ones, \
+/// deepest first, until nothing more can be unmounted, then remount the root read-only
+pub(crate) fn unmount_all() {
+    loop {
+        let table = match std::fs::read_to_string(PROC_MOUNTINFO) {
+            Ok(s) => MountTable::parse(&s),
+            Err(e) => {
+                println!("umount: failed to read {}: {}", PROC_MOUNTINFO, e);
+                break;
+            }
+        };
+        // the mounts without children, including the ones stacked on others
+        let leaves = table
+            .iter()
+            .filter(|m| {
+                m.mount_point.as_ref() != "/"
+                    && !is_api_mount(&m.mount_point)
+                    && table.children(m).next().is_none()
+            })
+            .collect::<Vec<_>>();
+        let mut progress = false;
+        for mount in leaves {
+            progress |= shutdown_unmount(&mount.mount_point);
         }
-    } else {
-        name.replace('-', "\\x2d").replace('/', "-")
-    } + ".mount")
-        .into()
+        if !progress {
+            break;
+        }
+    }
+    match mount_remount("/", MountFlags::RDONLY, "") {
+        Ok(_) => println!("umount: remounted / read-only"),
+        Err(e) => println!("umount: failed to remount / read-only: {}", e),
+    }
+}
+
+/// escape the path into a unit name with the suffix, like `systemd-escape --path --suffix`
+pub(crate) fn path_to_unit_name(path: &str, suffix: &str) -> String {
+    let name = match path.strip_prefix('/') {
+        Some("") => String::from('-'),
+        Some(s) => s.replace('-', "\\x2d").replace('/', "-"),
+        None => path.replace('-', "\\x2d").replace('/', "-"),
+    };
+    format!("{}.{}", name, suffix)
+}
+
+pub(crate) fn mount_point_to_unit_name(name: &str) -> String {
+    path_to_unit_name(name, "mount")
+}
+
+#[cfg(test)]
+mod test {
+    use rustix::{fs::MountFlags, mount::MountPropagationFlags};
+
+    use super::{bind_remount_flags, is_api_mount, MountOptions, MountTable, ProcMountInfoLine};
+
+    const MOUNTINFO: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+23 22 0:21 / /proc rw,nosuid,nodev,noexec,relatime shared:12 - proc proc rw
+24 22 8:2 / /home rw,relatime shared:2 - ext4 /dev/sda2 rw,errors=remount-ro
+25 24 8:3 / /home/my\\040data rw - ext4 /dev/sda3 rw
+26 22 8:2 /srv /mnt/srv rw,relatime shared:2 - ext4 /dev/sda2 rw
+";
+
+    #[test]
+    fn test_mountinfo_line() {
+        let line = ProcMountInfoLine::parse(
+            "36 35 98:0 /mnt1 /mnt\\0402 rw,noatime master:1 shared:2 - ext3 /dev/root rw,errors=continue",
+        )
+        .unwrap();
+        assert_eq!((line.mount_id, line.parent_id), (36, 35));
+        assert_eq!(line.st_dev, (98, 0));
+        assert_eq!(&*line.root, "/mnt1");
+        assert_eq!(&*line.mount_point, "/mnt 2");
+        assert_eq!(line.optional_fields.len(), 2);
+        assert_eq!(&*line.fs_type, "ext3");
+        assert_eq!(&*line.mount_source, "/dev/root");
+        assert_eq!(&*line.super_options, "rw,errors=continue");
+        assert!(ProcMountInfoLine::parse("36 35 98:0 /mnt1").is_none());
+    }
+
+    #[test]
+    fn test_mount_table() {
+        let table = MountTable::parse(MOUNTINFO);
+        let home = table.find("/home").unwrap();
+        let children: Vec<u32> = table.children(home).map(|m| m.mount_id).collect();
+        assert_eq!(children, [25]);
+
+        let new = MOUNTINFO
+            .replace("25 24 8:3 / /home/my\\040data rw - ext4 /dev/sda3 rw\n", "")
+            .replace("/proc rw,nosuid", "/proc ro,nosuid")
+            + "27 22 0:30 / /tmp rw - tmpfs tmpfs rw\n";
+        let new = MountTable::parse(&new);
+        let diff = table.diff(&new);
+        assert_eq!(
+            diff.removed.iter().map(|m| m.mount_id).collect::<Vec<_>>(),
+            [25]
+        );
+        assert_eq!(
+            diff.added.iter().map(|m| m.mount_id).collect::<Vec<_>>(),
+            [27]
+        );
+        assert_eq!(diff.changed.len(), 1);
+        assert!(table.diff(&table).is_empty());
+    }
+
+    #[test]
+    fn test_mount_options() {
+        let options = MountOptions::from("defaults,ro,noatime,nosuid,noauto,nofail,x-systemd.automount,_netdev,user,uid=1000,mode=0755");
+        assert_eq!(
+            options,
+            MountOptions {
+                flags: MountFlags::RDONLY | MountFlags::NOATIME | MountFlags::NOSUID,
+                propagation: MountPropagationFlags::empty(),
+                remount: false,
+                data: "uid=1000,mode=0755".into(),
+            }
+        );
+        let options = MountOptions::from("rbind,rslave,remount,ro");
+        assert_eq!(
+            options.flags,
+            MountFlags::BIND | MountFlags::REC | MountFlags::RDONLY
+        );
+        assert_eq!(
+            options.propagation,
+            MountPropagationFlags::SLAVE | MountPropagationFlags::REC
+        );
+        assert!(options.remount);
+        assert_eq!(options.data, "");
+        // overlay dirs are fs specific
+        assert_eq!(
+            MountOptions::from("lowerdir=/a:/b,upperdir=/u,workdir=/w").data,
+            "lowerdir=/a:/b,upperdir=/u,workdir=/w"
+        );
+        // later options override the former ones
+        assert_eq!(MountOptions::from("ro,rw").flags, MountFlags::empty());
+        assert_eq!(MountOptions::from("defaults").data, "");
+    }
+
+    #[test]
+    fn test_bind_remount_flags() {
+        let flags = |options: &str| bind_remount_flags(MountOptions::from(options).flags);
+        assert_eq!(
+            flags("bind,ro,nosuid,noatime"),
+            Some(MountFlags::BIND | MountFlags::RDONLY | MountFlags::NOSUID)
+        );
+        assert_eq!(
+            flags("rbind,nodev,noexec"),
+            Some(MountFlags::BIND | MountFlags::NODEV | MountFlags::NOEXEC)
+        );
+        assert_eq!(flags("bind"), None);
+        assert_eq!(flags("bind,rw"), None);
+        assert_eq!(flags("ro,nosuid"), None);
+    }
+
+    #[test]
+    fn test_api_mount() {
+        assert!(is_api_mount("/proc"));
+        assert!(is_api_mount("/sys/fs/cgroup"));
+        assert!(!is_api_mount("/"));
+        assert!(!is_api_mount("/system"));
+    }
 }
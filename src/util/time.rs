@@ -0,0 +1,94 @@
+use std::{fmt::Display, time::Duration};
+
+use rustix::time::{clock_gettime, ClockId};
+use tokio::time::Instant;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Error {
+    Empty,
+    Number(Box<str>),
+    Unit(Box<str>),
+    Range(Box<str>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Empty => write!(f, "empty time span"),
+            Error::Number(s) => write!(f, "invalid number `{}` in time span", s),
+            Error::Unit(s) => write!(f, "unknown unit `{}` in time span", s),
+            Error::Range(s) => write!(f, "time span `{}` out of range", s),
+        }
+    }
+}
+
+fn unit_secs(unit: &str) -> Option<f64> {
+    const MINUTE: f64 = 60.0;
+    const HOUR: f64 = 60.0 * MINUTE;
+    const DAY: f64 = 24.0 * HOUR;
+    Some(match unit {
+        "us" | "usec" => 1e-6,
+        "ms" | "msec" => 1e-3,
+        "" | "s" | "sec" | "second" | "seconds" => 1.0,
+        "m" | "min" | "minute" | "minutes" => MINUTE,
+        "h" | "hr" | "hour" | "hours" => HOUR,
+        "d" | "day" | "days" => DAY,
+        "w" | "week" | "weeks" => 7.0 * DAY,
+        "M" | "month" | "months" => 30.44 * DAY,
+        "y" | "year" | "years" => 365.25 * DAY,
+        _ => return None,
+    })
+}
+
+/// parse time span like systemd, e.g. `1h 30min`, `2d`, `500ms` \
+/// a number without unit is in seconds
+pub(crate) fn parse_timespan(s: &str) -> Result<Duration, Error> {
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err(Error::Empty);
+    }
+    let mut secs = 0.0;
+    while !rest.is_empty() {
+        let num_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let (num, tail) = rest.split_at(num_len);
+        let num: f64 = num.parse().map_err(|_| Error::Number(num.into()))?;
+        let tail = tail.trim_start();
+        let unit_len = tail
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        secs += num * unit_secs(unit).ok_or_else(|| Error::Unit(unit.into()))?;
+        rest = tail.trim_start();
+    }
+    Duration::try_from_secs_f64(secs).map_err(|_| Error::Range(s.into()))
+}
+
+/// the monotonic instant when the system booted
+pub(crate) fn boot_time() -> Instant {
+    let uptime = clock_gettime(ClockId::Monotonic);
+    let uptime = Duration::new(uptime.tv_sec as u64, uptime.tv_nsec as u32);
+    let now = Instant::now();
+    now.checked_sub(uptime).unwrap_or(now)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{parse_timespan, Error};
+
+    #[test]
+    fn test_parse_timespan() {
+        assert_eq!(parse_timespan("1h 30min"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_timespan("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_timespan("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_timespan(" "), Err(Error::Empty));
+        assert_eq!(parse_timespan("1x"), Err(Error::Unit("x".into())));
+        assert_eq!(
+            parse_timespan("99999999999999999999y"),
+            Err(Error::Range("99999999999999999999y".into()))
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use tokio::{fs, io};
+
+const PASSWD: &str = "/etc/passwd";
+const GROUP: &str = "/etc/group";
+
+/// find the id of the name in a `passwd`/`group` style database \
+/// `name:passwd:id:...`
+fn find_id(db: &str, name: &str) -> Option<u32> {
+    db.lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split(':'))
+        .find_map(|mut fields| {
+            if fields.next()? == name {
+                fields.nth(1)?.parse().ok()
+            } else {
+                None
+            }
+        })
+}
+
+async fn lookup(db_path: &str, name: &str) -> io::Result<u32> {
+    // numeric ids are used directly
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+    let db = fs::read_to_string(db_path).await?;
+    find_id(&db, name).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("`{name}` not found in {db_path}"),
+        )
+    })
+}
+
+/// resolve a user name or a numeric uid
+pub(crate) async fn lookup_user(name: &str) -> io::Result<u32> {
+    lookup(PASSWD, name).await
+}
+
+/// resolve a group name or a numeric gid
+pub(crate) async fn lookup_group(name: &str) -> io::Result<u32> {
+    lookup(GROUP, name).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find_id, lookup};
+
+    #[test]
+    fn test_find_id() {
+        let db = "# comment\nroot:x:0:0:root:/root:/bin/sh\nnobody:x:65534:65534::/:/sbin/nologin\nbroken\n";
+        assert_eq!(find_id(db, "root"), Some(0));
+        assert_eq!(find_id(db, "nobody"), Some(65534));
+        assert_eq!(find_id(db, "broken"), None);
+        assert_eq!(find_id(db, "missing"), None);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let db = std::env::temp_dir().join(format!("sysrs-group-{}", std::process::id()));
+        std::fs::write(&db, "wheel:x:10:alice\n").unwrap();
+        let db_path = db.to_str().unwrap();
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                assert_eq!(lookup(db_path, "wheel").await.unwrap(), 10);
+                // numeric ids are not looked up at all
+                assert_eq!(lookup(db_path, "42").await.unwrap(), 42);
+                assert_eq!(lookup("/nonexistent", "7").await.unwrap(), 7);
+                let e = lookup(db_path, "missing").await.unwrap_err();
+                assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+            });
+        std::fs::remove_file(&db).unwrap();
+    }
+}